@@ -0,0 +1,379 @@
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use crate::authentication;
+use crate::config::{self, Config, SharedConfig};
+use crate::logging::{log_error, log_info};
+use crate::server_registry::{ServerId, ServerState};
+use crate::{MatchSlot, MatchmakerState, WaitingPlayer};
+
+// admin connections are mostly used by people typing the commands by hand
+const ADMIN_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[derive(Debug, PartialEq)]
+enum AdminCommand {
+    ListServers,
+    ListQueue,
+    KillServer(ServerId),
+    Pause,
+    Resume,
+    ReloadConfig,
+    GetRating(String),
+}
+
+/// Accepts admin connections on a separate thread, every admin connection gets its own thread
+pub fn start_admin_thread(
+    listener: TcpListener,
+    state: Arc<Mutex<MatchmakerState>>,
+    config: Arc<SharedConfig>,
+    config_path: String,
+) {
+    let config_path = Arc::new(config_path);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let state = state.clone();
+                    let config = config.clone();
+                    let config_path = config_path.clone();
+                    std::thread::spawn(move || {
+                        handle_admin_connection(stream, &state, &config, &config_path);
+                    });
+                }
+                Err(error) => {
+                    log_error!("Problem accepting an admin connection: {:?}", error);
+                }
+            }
+        }
+    });
+}
+
+fn handle_admin_connection(
+    stream: TcpStream,
+    state: &Mutex<MatchmakerState>,
+    config: &SharedConfig,
+    config_path: &str,
+) {
+    crate::serve_line_protocol(&stream, ADMIN_READ_TIMEOUT, |request_line| {
+        let words: Vec<String> = request_line
+            .split_whitespace()
+            .map(|s| s.to_string())
+            .collect();
+
+        let result = parse_admin_command(&words).and_then(|command| {
+            log_info!("Admin command: {:?}", command);
+            process_admin_command(command, state, config, config_path)
+        });
+
+        // every response ends with a status line, so the end of a multiline response can be found
+        match result {
+            Ok(lines) => {
+                lines
+                    .into_iter()
+                    .map(|line| line + "\n")
+                    .collect::<String>()
+                    + "ok\n"
+            }
+            Err(message) => format!("error: {}\n", message),
+        }
+    })
+}
+
+fn parse_admin_command(words: &[String]) -> Result<AdminCommand, String> {
+    match words {
+        [command] if command == "list-servers" => Ok(AdminCommand::ListServers),
+        [command] if command == "list-queue" => Ok(AdminCommand::ListQueue),
+        [command, server_id] if command == "kill-server" => match server_id.parse() {
+            Ok(server_id) => Ok(AdminCommand::KillServer(server_id)),
+            Err(_) => Err(format!("invalid server id '{}'", server_id)),
+        },
+        [command] if command == "pause" => Ok(AdminCommand::Pause),
+        [command] if command == "resume" => Ok(AdminCommand::Resume),
+        [command] if command == "reload-config" => Ok(AdminCommand::ReloadConfig),
+        [command, player_id] if command == "get-rating" => {
+            Ok(AdminCommand::GetRating(player_id.clone()))
+        }
+        _ => Err(format!("unknown command '{}'", words.join(" "))),
+    }
+}
+
+fn process_admin_command(
+    command: AdminCommand,
+    state: &Mutex<MatchmakerState>,
+    config: &SharedConfig,
+    config_path: &str,
+) -> Result<Vec<String>, String> {
+    match command {
+        AdminCommand::ListServers => Ok(list_servers(&state.lock().unwrap())),
+        AdminCommand::ListQueue => Ok(list_queue(&state.lock().unwrap(), &config.get())),
+        AdminCommand::KillServer(server_id) => kill_server(&mut state.lock().unwrap(), server_id),
+        AdminCommand::Pause => {
+            state.lock().unwrap().is_matchmaking_paused = true;
+            log_info!("Matchmaking is paused");
+            Ok(Vec::new())
+        }
+        AdminCommand::Resume => {
+            state.lock().unwrap().is_matchmaking_paused = false;
+            log_info!("Matchmaking is resumed");
+            Ok(Vec::new())
+        }
+        AdminCommand::ReloadConfig => reload_config(state, config, config_path),
+        AdminCommand::GetRating(player_id) => {
+            match state.lock().unwrap().player_ratings.get(&player_id) {
+                Some(rating) => Ok(vec![format!("player:{} rating:{:.1}", player_id, rating)]),
+                None => Err(format!("player '{}' has no rating yet", player_id)),
+            }
+        }
+    }
+}
+
+fn list_servers(state: &MatchmakerState) -> Vec<String> {
+    state
+        .server_registry
+        .get_servers()
+        .into_iter()
+        .map(|server| {
+            let server_state = match server.state {
+                ServerState::Running => "running",
+                _ => "stopping",
+            };
+            format!(
+                "server:{} port:{} pid:{} state:{} players:{} started:{} directory:{}",
+                server.id,
+                server.port,
+                server.pid,
+                server_state,
+                server.connected_players.len(),
+                server.start_time.format("%Y-%m-%d %H:%M:%S"),
+                server.working_directory
+            )
+        })
+        .collect()
+}
+
+fn list_queue(state: &MatchmakerState, config: &Config) -> Vec<String> {
+    let mut game_mode_names: Vec<&String> = state.open_matches.keys().collect();
+    game_mode_names.sort();
+
+    let mut lines = Vec::new();
+    for game_mode_name in game_mode_names {
+        let players_per_match = match config.get_game_mode(Some(game_mode_name)) {
+            Some(game_mode) => game_mode.players_per_match.to_string(),
+            None => "?".to_string(),
+        };
+        for slot in &state.open_matches[game_mode_name] {
+            for player in &slot.players_waiting {
+                lines.push(format_queued_player(
+                    game_mode_name,
+                    slot,
+                    player,
+                    &players_per_match,
+                ));
+            }
+        }
+    }
+    lines
+}
+
+/// Describes a player that waits for a match along with the match it is going to play
+fn format_queued_player(
+    game_mode_name: &str,
+    slot: &MatchSlot,
+    player: &WaitingPlayer,
+    players_per_match: &str,
+) -> String {
+    let mut line = format!("mode:{}", game_mode_name);
+    // anonymous players have no id to show
+    if let Some(player_id) = &player.player_id {
+        line += &format!(" player:{}", player_id);
+    }
+    line += &format!(
+        " waiting:{}s rating:{} match_players:{}/{} server:{}",
+        player.queued_at.elapsed().as_secs(),
+        player
+            .rating
+            .map_or("none".to_string(), |rating| rating.to_string()),
+        slot.players_waiting.len(),
+        players_per_match,
+        slot.server_id
+            .map_or("none".to_string(), |server_id| server_id.to_string())
+    );
+    if player.disconnected_at.is_some() {
+        line += " disconnected";
+    }
+    line
+}
+
+fn kill_server(state: &mut MatchmakerState, server_id: ServerId) -> Result<Vec<String>, String> {
+    if state.server_registry.get(server_id).is_none() {
+        return Err(format!("there is no running server {}", server_id));
+    }
+
+    for slots in state.open_matches.values_mut() {
+        slots.retain(|slot| slot.server_id != Some(server_id));
+    }
+    state.server_registry.kill_server(server_id);
+    log_info!("Dedicated server {} is killed by admin", server_id);
+    Ok(Vec::new())
+}
+
+fn reload_config(
+    state: &Mutex<MatchmakerState>,
+    shared_config: &SharedConfig,
+    config_path: &str,
+) -> Result<Vec<String>, String> {
+    let new_config = config::read_config(config_path)?;
+    if !crate::validate_config(&new_config) {
+        return Err("config is not valid, see the matchmaker output for details".to_string());
+    }
+    // the allowlist file is read again too
+    let authenticator = authentication::create_authenticator(&new_config.authentication)
+        .map_err(|error| format!("problem setting up authentication: {}", error))?;
+    // the certificate can also be replaced with a new one from other paths
+    let tls_certificates = state.lock().unwrap().tls_certificates.clone();
+    if let (Some(tls_certificates), Some(tls_config)) = (tls_certificates, &new_config.tls) {
+        tls_certificates
+            .reload(tls_config)
+            .map_err(|error| format!("problem loading TLS certificate: {}", error))?;
+    }
+
+    let old_config = shared_config.get();
+    let mut lines = Vec::new();
+    for (field_name, is_changed) in [
+        (
+            "network_interface",
+            old_config.network_interface != new_config.network_interface,
+        ),
+        (
+            "matchmaker_port",
+            old_config.matchmaker_port != new_config.matchmaker_port,
+        ),
+        (
+            "max_simultaneous_clients",
+            old_config.max_simultaneous_clients != new_config.max_simultaneous_clients,
+        ),
+        (
+            "admin_address",
+            old_config.admin_address != new_config.admin_address,
+        ),
+        (
+            "metrics_address",
+            old_config.metrics_address != new_config.metrics_address,
+        ),
+        (
+            "server_callback_address",
+            old_config.server_callback_address != new_config.server_callback_address,
+        ),
+        ("logging", old_config.logging != new_config.logging),
+        ("tls", old_config.tls.is_some() != new_config.tls.is_some()),
+    ] {
+        if is_changed {
+            lines.push(format!(
+                "change of {} is applied only after restart",
+                field_name
+            ));
+        }
+    }
+
+    {
+        let mut state = state.lock().unwrap();
+        let state = &mut *state;
+        // the connections that are already open keep using the previous authenticator
+        state.authenticator = authenticator;
+        state.port_reservations.set_port_range(
+            new_config.first_dedicated_server_port..=new_config.last_dedicated_server_port,
+        );
+
+        let server_registry = &mut state.server_registry;
+        state.open_matches.retain(|game_mode_name, slots| {
+            if new_config.get_game_mode(Some(game_mode_name)).is_some() {
+                return true;
+            }
+            // the game mode is removed, nobody is going to play on these servers
+            for server_id in slots.iter().filter_map(|slot| slot.server_id) {
+                server_registry.stop_server(server_id);
+            }
+            false
+        });
+
+        // the matches that became full with the new number of players get their servers
+        // on the next maintenance
+    }
+
+    shared_config.replace(new_config);
+    log_info!("Config is reloaded from '{}'", config_path);
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_admin_commands_are_parsed() {
+        assert_eq!(
+            parse_admin_command(&words("list-servers")),
+            Ok(AdminCommand::ListServers)
+        );
+        assert_eq!(
+            parse_admin_command(&words("kill-server 12")),
+            Ok(AdminCommand::KillServer(12))
+        );
+        assert_eq!(
+            parse_admin_command(&words("reload-config")),
+            Ok(AdminCommand::ReloadConfig)
+        );
+        assert_eq!(
+            parse_admin_command(&words("get-rating player1")),
+            Ok(AdminCommand::GetRating("player1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_queued_players_are_listed_with_their_match() {
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let player = WaitingPlayer {
+            wait_id: 1,
+            player_id: Some("alice".to_string()),
+            rating: Some(1500.0),
+            queued_at: std::time::Instant::now(),
+            disconnected_at: None,
+            match_found_sender: sender.clone(),
+        };
+        let anonymous_player = WaitingPlayer {
+            wait_id: 2,
+            player_id: None,
+            rating: None,
+            queued_at: std::time::Instant::now(),
+            disconnected_at: Some(std::time::Instant::now()),
+            match_found_sender: sender,
+        };
+        let slot = MatchSlot {
+            server_id: None,
+            created_at: std::time::Instant::now(),
+            players_waiting: vec![player, anonymous_player],
+            respawns_count: 0,
+            fill_with_bots: false,
+        };
+
+        assert_eq!(
+            format_queued_player("casual", &slot, &slot.players_waiting[0], "4"),
+            "mode:casual player:alice waiting:0s rating:1500 match_players:2/4 server:none"
+        );
+        assert_eq!(
+            format_queued_player("casual", &slot, &slot.players_waiting[1], "4"),
+            "mode:casual waiting:0s rating:none match_players:2/4 server:none disconnected"
+        );
+    }
+
+    #[test]
+    fn test_invalid_admin_commands_produce_error() {
+        assert!(parse_admin_command(&words("kill-server first")).is_err());
+        assert!(parse_admin_command(&words("pause now")).is_err());
+        assert!(parse_admin_command(&words("")).is_err());
+    }
+}
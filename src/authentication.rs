@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::AuthenticationConfig;
+
+/// Checks that the player that sent the hello request is who it claims to be
+pub trait Authenticator: Send + Sync {
+    /// Returns the reason if the auth value doesn't prove the identity of the player
+    fn authenticate(&self, player_id: &str, auth: &str) -> Result<(), String>;
+
+    /// Whether a successful authentication proves the identity of the player,
+    /// otherwise the auth value is only a secret shared between the connections of the player
+    fn is_identity_verified(&self) -> bool {
+        true
+    }
+}
+
+/// Accepts everybody, the players are trusted to send their own ids
+pub struct NoAuthenticator;
+
+/// Verifies tickets signed by the game backend with a secret shared with the matchmaker,
+/// a ticket is "<expires_at>.<signature>" where expires_at is a unix timestamp in seconds
+/// and signature is the hex encoded HMAC-SHA256 of "<player_id>.<expires_at>"
+pub struct HmacAuthenticator {
+    secret: Vec<u8>,
+}
+
+/// Accepts the players listed in a file with their auth values
+pub struct AllowlistAuthenticator {
+    auth_by_player_id: HashMap<String, String>,
+}
+
+impl Authenticator for NoAuthenticator {
+    fn authenticate(&self, _player_id: &str, _auth: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn is_identity_verified(&self) -> bool {
+        false
+    }
+}
+
+impl HmacAuthenticator {
+    pub fn new(secret: &str) -> Result<HmacAuthenticator, String> {
+        if secret.is_empty() {
+            return Err("hmac secret should not be empty".to_string());
+        }
+        Ok(HmacAuthenticator {
+            secret: secret.as_bytes().to_vec(),
+        })
+    }
+
+    fn create_mac(&self, player_id: &str, expires_at: &str) -> Hmac<Sha256> {
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(player_id.as_bytes());
+        mac.update(b".");
+        mac.update(expires_at.as_bytes());
+        mac
+    }
+}
+
+impl Authenticator for HmacAuthenticator {
+    fn authenticate(&self, player_id: &str, auth: &str) -> Result<(), String> {
+        let (expires_at, signature) = match auth.split_once('.') {
+            Some(parts) => parts,
+            None => return Err("ticket should be <expires_at>.<signature>".to_string()),
+        };
+        let expires_at_seconds: i64 = match expires_at.parse() {
+            Ok(expires_at_seconds) => expires_at_seconds,
+            Err(_) => return Err("ticket expiration time is not a number".to_string()),
+        };
+        let signature = match decode_hex(signature) {
+            Some(signature) => signature,
+            None => return Err("ticket signature is not a hex string".to_string()),
+        };
+
+        // the signature is checked in constant time to not reveal how much of it is correct
+        if self
+            .create_mac(player_id, expires_at)
+            .verify_slice(&signature)
+            .is_err()
+        {
+            return Err("ticket signature is not valid".to_string());
+        }
+        if Utc::now().timestamp() >= expires_at_seconds {
+            return Err("ticket is expired".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl AllowlistAuthenticator {
+    /// Reads the file with one "<player_id> <auth>" pair per line,
+    /// empty lines and lines starting with # are skipped
+    pub fn load(file_path: &str) -> Result<AllowlistAuthenticator, String> {
+        let data = std::fs::read_to_string(file_path)
+            .map_err(|error| format!("problem reading '{}': {}", file_path, error))?;
+        AllowlistAuthenticator::parse(&data)
+            .map_err(|error| format!("problem parsing '{}': {}", file_path, error))
+    }
+
+    fn parse(data: &str) -> Result<AllowlistAuthenticator, String> {
+        let mut auth_by_player_id = HashMap::new();
+        for (line_index, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [player_id, auth] => {
+                    auth_by_player_id.insert(player_id.to_string(), auth.to_string());
+                }
+                _ => {
+                    return Err(format!(
+                        "line {} should be <player_id> <auth>",
+                        line_index + 1
+                    ))
+                }
+            }
+        }
+        Ok(AllowlistAuthenticator { auth_by_player_id })
+    }
+}
+
+impl Authenticator for AllowlistAuthenticator {
+    fn authenticate(&self, player_id: &str, auth: &str) -> Result<(), String> {
+        match self.auth_by_player_id.get(player_id) {
+            Some(expected_auth) if expected_auth == auth => Ok(()),
+            Some(_) => Err("auth doesn't match the allowlist".to_string()),
+            None => Err("player is not in the allowlist".to_string()),
+        }
+    }
+}
+
+pub fn create_authenticator(
+    config: &AuthenticationConfig,
+) -> Result<Arc<dyn Authenticator>, String> {
+    match config {
+        AuthenticationConfig::None => Ok(Arc::new(NoAuthenticator)),
+        AuthenticationConfig::Hmac { secret } => Ok(Arc::new(HmacAuthenticator::new(secret)?)),
+        AuthenticationConfig::Allowlist { file_path } => {
+            Ok(Arc::new(AllowlistAuthenticator::load(file_path)?))
+        }
+    }
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(authenticator: &HmacAuthenticator, player_id: &str, expires_at: i64) -> String {
+        let signature = authenticator
+            .create_mac(player_id, &expires_at.to_string())
+            .finalize()
+            .into_bytes();
+        let signature: String = signature
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("{}.{}", expires_at, signature)
+    }
+
+    #[test]
+    fn test_hmac_ticket_is_verified() {
+        let authenticator = HmacAuthenticator::new("secret").unwrap();
+        let expires_at = Utc::now().timestamp() + 60;
+        let ticket = sign(&authenticator, "player1", expires_at);
+
+        assert!(authenticator.authenticate("player1", &ticket).is_ok());
+        assert!(authenticator.authenticate("player2", &ticket).is_err());
+        assert!(authenticator
+            .authenticate("player1", &ticket.replace(&expires_at.to_string(), "0"))
+            .is_err());
+        assert!(authenticator.authenticate("player1", "garbage").is_err());
+    }
+
+    #[test]
+    fn test_expired_hmac_ticket_is_rejected() {
+        let authenticator = HmacAuthenticator::new("secret").unwrap();
+        let ticket = sign(&authenticator, "player1", Utc::now().timestamp() - 1);
+        assert_eq!(
+            authenticator.authenticate("player1", &ticket),
+            Err("ticket is expired".to_string())
+        );
+    }
+
+    #[test]
+    fn test_allowlist_is_parsed() {
+        let authenticator =
+            AllowlistAuthenticator::parse("# testers\nalice secret1\n\nbob  secret2\n").unwrap();
+        assert!(authenticator.authenticate("alice", "secret1").is_ok());
+        assert!(authenticator.authenticate("bob", "secret2").is_ok());
+        assert!(authenticator.authenticate("alice", "secret2").is_err());
+        assert!(authenticator.authenticate("carol", "secret1").is_err());
+
+        assert!(AllowlistAuthenticator::parse("alice\n").is_err());
+    }
+}
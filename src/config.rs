@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::sync::{Arc, RwLock};
 
 use crate::config_updaters;
 
@@ -9,7 +10,169 @@ pub struct Config {
     pub working_directiries_path: String,
     pub dedicated_server_dir: String,
     pub network_interface: String,
+    pub public_address: String,
     pub matchmaker_port: u16,
+    pub first_dedicated_server_port: u16,
+    pub last_dedicated_server_port: u16,
+    pub max_simultaneous_clients: usize,
+    pub game_modes: Vec<GameModeConfig>,
+    pub initial_rating_window: f32,
+    pub rating_window_growth_per_second: f32,
+    pub instance_retention: InstanceRetentionConfig,
+    pub shutdown_timeout_seconds: u64,
+    pub admin_address: Option<String>,
+    pub metrics_address: Option<String>,
+    pub logging: LoggingConfig,
+    pub max_server_output_size_bytes: Option<u64>,
+    pub server_startup_timeout_seconds: u64,
+    pub server_readiness: ServerReadinessConfig,
+    pub max_server_respawns: u32,
+    pub server_callback_address: Option<String>,
+    pub player_ratings: PlayerRatingsConfig,
+    pub warm_server_idle_timeout_seconds: Option<u64>,
+    pub max_concurrent_servers: Option<usize>,
+    pub admission_control: AdmissionControlConfig,
+    pub server_full_retry_after_seconds: u64,
+    pub authentication: AuthenticationConfig,
+    pub allow_anonymous_players: bool,
+    pub tls: Option<TlsConfig>,
+    pub session_reconnect_timeout_seconds: u64,
+    pub max_queue_wait_seconds: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameModeConfig {
+    pub name: String,
+    pub players_per_match: usize,
+    pub dedicated_server_arguments: Vec<String>,
+    pub resources_dir: String,
+    pub warm_servers_count: usize,
+    // the match starts with bots in place of the missing players once the queue wait is over
+    pub fill_with_bots: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+    Delete,
+    Archive,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstanceRetentionConfig {
+    pub action: RetentionAction,
+    pub archive_path: String,
+    pub max_age_seconds: Option<u64>,
+    pub max_count: Option<usize>,
+    pub keep_crashed: bool,
+}
+
+/// How the matchmaker finds out that a dedicated server is ready to accept players
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ServerReadinessConfig {
+    // the server is considered ready right after it is started
+    None,
+    // the server is ready once it opens its UDP port
+    UdpPort,
+    // the server is ready once it prints a line containing the given text to its stdout
+    StdoutLine { line: String },
+    // the server is ready once it reports that through the server callback address
+    Callback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+    // the log is written to stdout if no file is set
+    pub file_path: Option<String>,
+    pub max_file_size_bytes: Option<u64>,
+    pub rotation_interval_seconds: Option<u64>,
+    pub max_rotated_files: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerRatingsConfig {
+    // the ratings are kept only in memory if no file is set
+    pub file_path: Option<String>,
+    pub initial_rating: f32,
+    pub k_factor: f32,
+}
+
+/// How the matchmaker checks the auth value that players send with the hello request
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum AuthenticationConfig {
+    // the auth value is not checked
+    None,
+    // the auth value is a ticket signed by the game backend with the shared secret
+    Hmac { secret: String },
+    // the auth value should match the one listed for the player in the file
+    Allowlist { file_path: String },
+}
+
+/// PEM files of the certificate chain and the private key of the matchmaker listener
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub certificate_path: String,
+    pub private_key_path: String,
+}
+
+/// Limits of the host resources, no new servers are started while any of them is exceeded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdmissionControlConfig {
+    // load average of the last minute divided by the number of CPUs
+    pub max_load_average_per_cpu: Option<f32>,
+    pub min_available_memory_bytes: Option<u64>,
+}
+
+impl Config {
+    /// Returns the game mode with the given name, or the first game mode if no name is given
+    pub fn get_game_mode(&self, name: Option<&str>) -> Option<&GameModeConfig> {
+        match name {
+            Some(name) => self.game_modes.iter().find(|mode| mode.name == name),
+            None => self.game_modes.first(),
+        }
+    }
+}
+
+/// Config that can be replaced while the matchmaker is running,
+/// the code that needs the config takes the latest version each time it starts doing something
+pub struct SharedConfig {
+    config: RwLock<Arc<Config>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig {
+            config: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, config: Config) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
 }
 
 pub fn read_config(config_path: &str) -> Result<Config, String> {
@@ -40,14 +203,73 @@ pub fn read_config(config_path: &str) -> Result<Config, String> {
     return Ok(config);
 }
 
-pub fn generate_default_config(config_path: &str) {
-    let default_config = Config {
+pub fn get_default_config() -> Config {
+    Config {
         working_directiries_path: "instances".to_string(),
         dedicated_server_dir: ".".to_string(),
         network_interface: "0.0.0.0".to_string(),
+        // players can't connect to localhost, so the operator has to set the real address
+        public_address: String::new(),
         matchmaker_port: 14736,
+        first_dedicated_server_port: 8000,
+        last_dedicated_server_port: 8999,
+        max_simultaneous_clients: 256,
+        game_modes: vec![GameModeConfig {
+            name: "default".to_string(),
+            players_per_match: 2,
+            dedicated_server_arguments: Vec::new(),
+            resources_dir: "resources".to_string(),
+            warm_servers_count: 0,
+            fill_with_bots: false,
+        }],
+        initial_rating_window: 100.0,
+        rating_window_growth_per_second: 10.0,
+        instance_retention: InstanceRetentionConfig {
+            action: RetentionAction::Delete,
+            archive_path: "archive".to_string(),
+            max_age_seconds: Some(7 * 24 * 60 * 60),
+            max_count: Some(1000),
+            keep_crashed: true,
+        },
+        shutdown_timeout_seconds: 0,
+        admin_address: Some("127.0.0.1:14737".to_string()),
+        metrics_address: None,
+        logging: LoggingConfig {
+            level: LogLevel::Info,
+            format: LogFormat::Text,
+            file_path: None,
+            max_file_size_bytes: Some(100 * 1024 * 1024),
+            rotation_interval_seconds: Some(24 * 60 * 60),
+            max_rotated_files: 5,
+        },
+        max_server_output_size_bytes: Some(10 * 1024 * 1024),
+        server_startup_timeout_seconds: 30,
+        server_readiness: ServerReadinessConfig::UdpPort,
+        max_server_respawns: 3,
+        server_callback_address: Some("127.0.0.1:14738".to_string()),
+        player_ratings: PlayerRatingsConfig {
+            file_path: Some("data/player_ratings.json".to_string()),
+            initial_rating: 1500.0,
+            k_factor: 32.0,
+        },
+        warm_server_idle_timeout_seconds: Some(10 * 60),
+        max_concurrent_servers: Some(1000),
+        admission_control: AdmissionControlConfig {
+            max_load_average_per_cpu: None,
+            min_available_memory_bytes: None,
+        },
+        server_full_retry_after_seconds: 10,
+        authentication: AuthenticationConfig::None,
+        allow_anonymous_players: true,
+        tls: None,
+        session_reconnect_timeout_seconds: 30,
+        max_queue_wait_seconds: Some(300),
         config_format_version: config_updaters::LATEST_CONFIG_VERSION.to_string(),
-    };
+    }
+}
+
+pub fn generate_default_config(config_path: &str) {
+    let default_config = get_default_config();
 
     let default_config_json = serde_json::to_string_pretty(&default_config).unwrap();
 
@@ -2,7 +2,7 @@ use crate::json_file_updater::{JsonFileUpdater, UpdateResult};
 use serde_json::Value as JsonValue;
 
 static VERSION_FIELD_NAME: &str = "config_format_version";
-pub static LATEST_CONFIG_VERSION: &str = "0.0.2";
+pub static LATEST_CONFIG_VERSION: &str = "0.0.23";
 
 pub fn update_config_to_the_latest_version(
     mut config_json: JsonValue,
@@ -33,6 +33,122 @@ fn register_config_updaters() -> JsonFileUpdater {
     json_config_updater.add_update_function("0.0.2", |config_json| {
         config_json["network_interface"] = JsonValue::String("0.0.0.0".to_string());
     });
+    json_config_updater.add_update_function("0.0.3", |config_json| {
+        config_json["max_simultaneous_clients"] = JsonValue::from(256);
+    });
+    json_config_updater.add_update_function("0.0.4", |config_json| {
+        config_json["players_per_match"] = JsonValue::from(2);
+    });
+    json_config_updater.add_update_function("0.0.5", |config_json| {
+        let players_per_match = config_json["players_per_match"].take();
+        config_json
+            .as_object_mut()
+            .unwrap()
+            .remove("players_per_match");
+        config_json["game_modes"] = serde_json::json!([{
+            "name": "default",
+            "players_per_match": players_per_match,
+            "dedicated_server_arguments": [],
+            "resources_dir": "resources",
+        }]);
+    });
+    json_config_updater.add_update_function("0.0.6", |config_json| {
+        config_json["initial_rating_window"] = JsonValue::from(100.0);
+        config_json["rating_window_growth_per_second"] = JsonValue::from(10.0);
+    });
+    json_config_updater.add_update_function("0.0.7", |config_json| {
+        // the address players reach a wildcard interface by can't be guessed,
+        // the config doesn't pass validation until it is set
+        let public_address = match config_json["network_interface"].as_str() {
+            Some("0.0.0.0") | Some("::") | None => String::new(),
+            Some(network_interface) => network_interface.to_string(),
+        };
+        config_json["public_address"] = JsonValue::String(public_address);
+    });
+    json_config_updater.add_update_function("0.0.8", |config_json| {
+        config_json["first_dedicated_server_port"] = JsonValue::from(8000);
+        config_json["last_dedicated_server_port"] = JsonValue::from(8999);
+    });
+    json_config_updater.add_update_function("0.0.9", |config_json| {
+        config_json["instance_retention"] = serde_json::json!({
+            "action": "delete",
+            "archive_path": "archive",
+            "max_age_seconds": 604800,
+            "max_count": 1000,
+            "keep_crashed": true,
+        });
+    });
+    json_config_updater.add_update_function("0.0.10", |config_json| {
+        config_json["shutdown_timeout_seconds"] = JsonValue::from(0);
+    });
+    json_config_updater.add_update_function("0.0.11", |config_json| {
+        config_json["admin_address"] = JsonValue::String("127.0.0.1:14737".to_string());
+    });
+    json_config_updater.add_update_function("0.0.12", |config_json| {
+        config_json["metrics_address"] = JsonValue::Null;
+    });
+    json_config_updater.add_update_function("0.0.13", |config_json| {
+        config_json["logging"] = serde_json::json!({
+            "level": "info",
+            "format": "text",
+            "file_path": null,
+            "max_file_size_bytes": null,
+            "rotation_interval_seconds": null,
+            "max_rotated_files": 5,
+        });
+    });
+    json_config_updater.add_update_function("0.0.14", |config_json| {
+        config_json["max_server_output_size_bytes"] = JsonValue::Null;
+    });
+    json_config_updater.add_update_function("0.0.15", |config_json| {
+        config_json["server_startup_timeout_seconds"] = JsonValue::from(30);
+        config_json["max_server_respawns"] = JsonValue::from(3);
+    });
+    json_config_updater.add_update_function("0.0.16", |config_json| {
+        config_json["server_readiness"] = serde_json::json!({ "method": "udp_port" });
+    });
+    json_config_updater.add_update_function("0.0.17", |config_json| {
+        config_json["server_callback_address"] = JsonValue::Null;
+        config_json["player_ratings"] = serde_json::json!({
+            "file_path": null,
+            "initial_rating": 1500.0,
+            "k_factor": 32.0,
+        });
+    });
+    json_config_updater.add_update_function("0.0.18", |config_json| {
+        if let Some(game_modes) = config_json["game_modes"].as_array_mut() {
+            for game_mode in game_modes {
+                game_mode["warm_servers_count"] = JsonValue::from(0);
+            }
+        }
+        config_json["warm_server_idle_timeout_seconds"] = JsonValue::from(600);
+    });
+    json_config_updater.add_update_function("0.0.19", |config_json| {
+        config_json["max_concurrent_servers"] = JsonValue::Null;
+        config_json["admission_control"] = serde_json::json!({
+            "max_load_average_per_cpu": null,
+            "min_available_memory_bytes": null,
+        });
+        config_json["server_full_retry_after_seconds"] = JsonValue::from(10);
+    });
+    json_config_updater.add_update_function("0.0.20", |config_json| {
+        config_json["authentication"] = serde_json::json!({ "method": "none" });
+        config_json["allow_anonymous_players"] = JsonValue::Bool(true);
+    });
+    json_config_updater.add_update_function("0.0.21", |config_json| {
+        config_json["tls"] = JsonValue::Null;
+    });
+    json_config_updater.add_update_function("0.0.22", |config_json| {
+        config_json["session_reconnect_timeout_seconds"] = JsonValue::from(30);
+    });
+    json_config_updater.add_update_function("0.0.23", |config_json| {
+        if let Some(game_modes) = config_json["game_modes"].as_array_mut() {
+            for game_mode in game_modes {
+                game_mode["fill_with_bots"] = JsonValue::Bool(false);
+            }
+        }
+        config_json["max_queue_wait_seconds"] = JsonValue::Null;
+    });
 
     // add update functions above this line
     // don't forget to update LATEST_CONFIG_VERSION at the beginning of the file
@@ -0,0 +1,54 @@
+use std::fs;
+
+const LOAD_AVERAGE_PATH: &str = "/proc/loadavg";
+const MEMORY_INFO_PATH: &str = "/proc/meminfo";
+
+/// Load average of the last minute divided by the number of CPUs,
+/// 1.0 means that all the CPUs of the host are busy
+pub fn get_load_average_per_cpu() -> Option<f32> {
+    let load_average = parse_load_average(&fs::read_to_string(LOAD_AVERAGE_PATH).ok()?)?;
+    let cpus_count = std::thread::available_parallelism().ok()?.get();
+    Some(load_average / cpus_count as f32)
+}
+
+/// Memory that can be given to new processes without swapping
+pub fn get_available_memory_bytes() -> Option<u64> {
+    parse_available_memory(&fs::read_to_string(MEMORY_INFO_PATH).ok()?)
+}
+
+fn parse_load_average(text: &str) -> Option<f32> {
+    // "0.52 0.58 0.59 1/1190 43124", the first number is the load average of the last minute
+    text.split_whitespace().next()?.parse().ok()
+}
+
+fn parse_available_memory(text: &str) -> Option<u64> {
+    // one "Name:   value kB" line per value
+    let line = text
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_average_is_parsed() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/1190 43124\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average(""), None);
+    }
+
+    #[test]
+    fn test_available_memory_is_parsed() {
+        let memory_info = "MemTotal:       16318412 kB\n\
+            MemFree:          523412 kB\n\
+            MemAvailable:    8000000 kB\n";
+        assert_eq!(parse_available_memory(memory_info), Some(8000000 * 1024));
+        assert_eq!(parse_available_memory("MemTotal: 16318412 kB\n"), None);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::{InstanceRetentionConfig, RetentionAction};
+use crate::logging::{log_error, log_info};
+use crate::server_output;
+
+struct FinishedInstance {
+    directory: PathBuf,
+    finished_at: SystemTime,
+}
+
+/// Keeps track of the working directories of the dedicated servers that have exited
+/// and removes or archives them according to the retention policy from the config
+pub struct InstanceRetention {
+    // sorted from the oldest to the newest
+    finished_instances: VecDeque<FinishedInstance>,
+}
+
+impl InstanceRetention {
+    pub fn new() -> InstanceRetention {
+        InstanceRetention {
+            finished_instances: VecDeque::new(),
+        }
+    }
+
+    /// Picks up the directories that were left by the previous runs of the matchmaker,
+    /// should be called before any new dedicated server is started. Only the directories
+    /// that the matchmaker created are picked up, the ones of crashed servers are kept if configured.
+    pub fn add_leftover_instances(
+        &mut self,
+        working_directories_path: &str,
+        config: &InstanceRetentionConfig,
+    ) {
+        let entries = match fs::read_dir(working_directories_path) {
+            Ok(entries) => entries,
+            Err(error) => {
+                log_error!(
+                    "Problem reading directory '{}': {:?}",
+                    working_directories_path,
+                    error
+                );
+                return;
+            }
+        };
+
+        let mut leftover_instances: Vec<FinishedInstance> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter(|entry| is_instance_directory_name(&entry.file_name().to_string_lossy()))
+            .filter(|entry| {
+                !(config.keep_crashed && server_output::is_crashed_instance(&entry.path()))
+            })
+            .map(|entry| FinishedInstance {
+                finished_at: entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or_else(|_| SystemTime::now()),
+                directory: entry.path(),
+            })
+            .collect();
+        leftover_instances.sort_by_key(|instance| instance.finished_at);
+
+        for instance in leftover_instances {
+            self.finished_instances.push_back(instance);
+        }
+    }
+
+    pub fn add_finished_instance(
+        &mut self,
+        directory: &str,
+        is_crashed: bool,
+        config: &InstanceRetentionConfig,
+    ) {
+        if is_crashed && config.keep_crashed {
+            log_info!("Keeping directory '{}' of the crashed server", directory);
+            return;
+        }
+
+        self.finished_instances.push_back(FinishedInstance {
+            directory: PathBuf::from(directory),
+            finished_at: SystemTime::now(),
+        });
+    }
+
+    pub fn apply_policy(&mut self, config: &InstanceRetentionConfig) {
+        let now = SystemTime::now();
+
+        while let Some(oldest_instance) = self.finished_instances.front() {
+            let is_over_max_count = match config.max_count {
+                Some(max_count) => self.finished_instances.len() > max_count,
+                None => false,
+            };
+            let is_over_max_age = match config.max_age_seconds {
+                Some(max_age_seconds) => {
+                    now.duration_since(oldest_instance.finished_at)
+                        .unwrap_or_default()
+                        > Duration::from_secs(max_age_seconds)
+                }
+                None => false,
+            };
+            if !is_over_max_count && !is_over_max_age {
+                break;
+            }
+
+            let instance = self.finished_instances.pop_front().unwrap();
+            if let Err(error) = retire_instance(&instance.directory, config) {
+                log_error!(
+                    "Problem cleaning up directory '{}': {:?}",
+                    instance.directory.to_string_lossy(),
+                    error
+                );
+            }
+        }
+    }
+}
+
+/// Checks that the directory is named like the working directories that the matchmaker creates,
+/// YYMMDD_HHMMSS_xxxxxxx
+fn is_instance_directory_name(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('_').collect();
+    match parts[..] {
+        [date, time, random_part] => {
+            date.len() == 6
+                && date.chars().all(|character| character.is_ascii_digit())
+                && time.len() == 6
+                && time.chars().all(|character| character.is_ascii_digit())
+                && random_part.len() == 7
+                && random_part
+                    .chars()
+                    .all(|character| character.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+fn retire_instance(directory: &Path, config: &InstanceRetentionConfig) -> std::io::Result<()> {
+    if config.action == RetentionAction::Archive {
+        archive_directory(directory, Path::new(&config.archive_path))?;
+    }
+    fs::remove_dir_all(directory)
+}
+
+fn archive_directory(directory: &Path, archive_path: &Path) -> std::io::Result<()> {
+    let directory_name = match directory.file_name() {
+        Some(directory_name) => directory_name,
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "directory has no name",
+            ))
+        }
+    };
+
+    fs::create_dir_all(archive_path)?;
+    let mut archive_file_name = directory_name.to_os_string();
+    archive_file_name.push(".tar.gz");
+    let archive_file = fs::File::create(archive_path.join(archive_file_name))?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(archive_file, Compression::default()));
+    // don't pack the shared resources that are linked to the directory
+    builder.follow_symlinks(false);
+    builder.append_dir_all(directory_name, directory)?;
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("retention_test_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn create_config(action: RetentionAction, archive_path: &Path) -> InstanceRetentionConfig {
+        InstanceRetentionConfig {
+            action,
+            archive_path: archive_path.to_string_lossy().to_string(),
+            max_age_seconds: None,
+            max_count: None,
+            keep_crashed: true,
+        }
+    }
+
+    fn add_instance(retention: &mut InstanceRetention, directory: PathBuf, age: Duration) {
+        fs::create_dir_all(&directory).unwrap();
+        retention.finished_instances.push_back(FinishedInstance {
+            directory,
+            finished_at: SystemTime::now() - age,
+        });
+    }
+
+    #[test]
+    fn test_oldest_instances_over_max_count_are_deleted() {
+        let directory = create_test_directory("max_count");
+        let mut config = create_config(RetentionAction::Delete, &directory.join("archive"));
+        config.max_count = Some(1);
+
+        let mut retention = InstanceRetention::new();
+        add_instance(
+            &mut retention,
+            directory.join("old"),
+            Duration::from_secs(20),
+        );
+        add_instance(
+            &mut retention,
+            directory.join("new"),
+            Duration::from_secs(10),
+        );
+        retention.apply_policy(&config);
+
+        assert!(!directory.join("old").exists());
+        assert!(directory.join("new").exists());
+        assert_eq!(retention.finished_instances.len(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_instances_over_max_age_are_archived() {
+        let directory = create_test_directory("max_age");
+        let archive_path = directory.join("archive");
+        let mut config = create_config(RetentionAction::Archive, &archive_path);
+        config.max_age_seconds = Some(60);
+
+        let mut retention = InstanceRetention::new();
+        add_instance(
+            &mut retention,
+            directory.join("old"),
+            Duration::from_secs(120),
+        );
+        add_instance(
+            &mut retention,
+            directory.join("new"),
+            Duration::from_secs(0),
+        );
+        retention.apply_policy(&config);
+
+        assert!(!directory.join("old").exists());
+        assert!(archive_path.join("old.tar.gz").is_file());
+        assert!(directory.join("new").exists());
+        assert!(!archive_path.join("new.tar.gz").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_only_leftover_instance_directories_are_picked_up() {
+        let directory = create_test_directory("leftovers");
+        let config = create_config(RetentionAction::Delete, &directory.join("archive"));
+        fs::create_dir_all(directory.join("261018_040049_ewjIF0y")).unwrap();
+        fs::create_dir_all(directory.join("261018_040050_Z0jpsFp")).unwrap();
+        fs::write(
+            directory
+                .join("261018_040050_Z0jpsFp")
+                .join(server_output::EXIT_METADATA_FILE_NAME),
+            r#"{"state": "crashed"}"#,
+        )
+        .unwrap();
+        fs::create_dir_all(directory.join("backups")).unwrap();
+
+        let mut retention = InstanceRetention::new();
+        retention.add_leftover_instances(&directory.to_string_lossy(), &config);
+        let picked_up: Vec<&Path> = retention
+            .finished_instances
+            .iter()
+            .map(|instance| instance.directory.as_path())
+            .collect();
+        assert_eq!(picked_up, vec![directory.join("261018_040049_ewjIF0y")]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_instance_directory_names_are_recognized() {
+        assert!(is_instance_directory_name("261018_040049_ewjIF0y"));
+        assert!(!is_instance_directory_name("archive"));
+        assert!(!is_instance_directory_name("261018_040049"));
+        assert!(!is_instance_directory_name("261018_040049_ewj"));
+        assert!(!is_instance_directory_name("2610_040049_ewjIF0y"));
+    }
+}
@@ -0,0 +1,343 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{LogFormat, LogLevel, LoggingConfig};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+thread_local! {
+    // describes what the current thread is working on, added to every log line written from it
+    static CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default());
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogContext {
+    pub peer_address: Option<String>,
+    pub request_id: Option<u64>,
+}
+
+struct Logger {
+    level: LogLevel,
+    format: LogFormat,
+    // None means stdout
+    file: Option<Mutex<RotatingFile>>,
+}
+
+/// Log file that is renamed to <path>.1 when it grows too big or too old, the previously
+/// rotated files are shifted to <path>.2, <path>.3 and so on, the oldest ones are removed
+struct RotatingFile {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    opened_at: SystemTime,
+    max_size: Option<u64>,
+    rotation_interval: Option<Duration>,
+    max_rotated_files: usize,
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::config::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warning {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::config::LogLevel::Warning, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::config::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::config::LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {log_debug, log_error, log_info, log_warning};
+
+/// Sets up the logger from the config, the messages logged before that go to stdout.
+/// level_override replaces the level from the config (e.g. when it is set from the command line)
+pub fn init(config: &LoggingConfig, level_override: Option<LogLevel>) -> std::io::Result<()> {
+    let file = match &config.file_path {
+        Some(file_path) => Some(Mutex::new(RotatingFile::open(
+            PathBuf::from(file_path),
+            config.max_file_size_bytes,
+            config.rotation_interval_seconds.map(Duration::from_secs),
+            config.max_rotated_files,
+        )?)),
+        None => None,
+    };
+
+    let logger = Logger {
+        level: level_override.unwrap_or(config.level),
+        format: config.format,
+        file,
+    };
+    if LOGGER.set(logger).is_err() {
+        log_warning!("Logger is already initialized");
+    }
+    Ok(())
+}
+
+pub fn parse_log_level(value: &str) -> Option<LogLevel> {
+    match value {
+        "error" => Some(LogLevel::Error),
+        "warning" => Some(LogLevel::Warning),
+        "info" => Some(LogLevel::Info),
+        "debug" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+pub fn set_context(context: LogContext) {
+    CONTEXT.with(|current| *current.borrow_mut() = context);
+}
+
+pub fn set_request_id(request_id: Option<u64>) {
+    CONTEXT.with(|current| current.borrow_mut().request_id = request_id);
+}
+
+pub fn log(level: LogLevel, message: std::fmt::Arguments) {
+    let (max_level, format, file) = match LOGGER.get() {
+        Some(logger) => (logger.level, logger.format, logger.file.as_ref()),
+        None => (LogLevel::Info, LogFormat::Text, None),
+    };
+    if level > max_level {
+        return;
+    }
+
+    let context = CONTEXT.with(|current| current.borrow().clone());
+    let line = format_log_line(format, Utc::now(), level, &context, &message.to_string());
+
+    match file {
+        Some(file) => {
+            if let Err(error) = file.lock().unwrap().write_line(&line) {
+                // there is no better place to report it
+                eprintln!("Problem writing to the log file: {:?}", error);
+                print!("{}", line);
+            }
+        }
+        None => print!("{}", line),
+    }
+}
+
+fn get_level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Error => "error",
+        LogLevel::Warning => "warning",
+        LogLevel::Info => "info",
+        LogLevel::Debug => "debug",
+    }
+}
+
+fn format_log_line(
+    format: LogFormat,
+    time: DateTime<Utc>,
+    level: LogLevel,
+    context: &LogContext,
+    message: &str,
+) -> String {
+    match format {
+        LogFormat::Text => {
+            let mut line = format!(
+                "{} {:<7}",
+                time.format("%Y-%m-%d %H:%M:%S%.3f"),
+                get_level_name(level).to_uppercase()
+            );
+            if let Some(peer_address) = &context.peer_address {
+                line += &format!(" [{}]", peer_address);
+            }
+            if let Some(request_id) = context.request_id {
+                line += &format!(" [request {}]", request_id);
+            }
+            line + " " + message + "\n"
+        }
+        LogFormat::Json => {
+            let mut object = serde_json::json!({
+                "time": time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                "level": get_level_name(level),
+                "message": message,
+            });
+            if let Some(peer_address) = &context.peer_address {
+                object["peer_address"] = serde_json::Value::from(peer_address.clone());
+            }
+            if let Some(request_id) = context.request_id {
+                object["request_id"] = serde_json::Value::from(request_id);
+            }
+            object.to_string() + "\n"
+        }
+    }
+}
+
+impl RotatingFile {
+    fn open(
+        path: PathBuf,
+        max_size: Option<u64>,
+        rotation_interval: Option<Duration>,
+        max_rotated_files: usize,
+    ) -> std::io::Result<RotatingFile> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            opened_at: SystemTime::now(),
+            max_size,
+            rotation_interval,
+            max_rotated_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.should_rotate(line.len() as u64) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn should_rotate(&self, next_line_size: u64) -> bool {
+        // an empty file is never rotated, even if one line doesn't fit into it
+        if self.size == 0 {
+            return false;
+        }
+        let is_too_big = match self.max_size {
+            Some(max_size) => self.size + next_line_size > max_size,
+            None => false,
+        };
+        let is_too_old = match self.rotation_interval {
+            Some(rotation_interval) => {
+                self.opened_at.elapsed().unwrap_or_default() >= rotation_interval
+            }
+            None => false,
+        };
+        is_too_big || is_too_old
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_rotated_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_rotated_files).rev() {
+                let rotated_path = get_rotated_path(&self.path, index);
+                if rotated_path.exists() {
+                    fs::rename(rotated_path, get_rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, get_rotated_path(&self.path, 1))?;
+        }
+
+        self.file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+fn get_rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated_path = path.as_os_str().to_os_string();
+    rotated_path.push(format!(".{}", index));
+    PathBuf::from(rotated_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn test_context() -> LogContext {
+        LogContext {
+            peer_address: Some("10.0.0.5:51000".to_string()),
+            request_id: Some(12),
+        }
+    }
+
+    #[test]
+    fn test_text_log_line_contains_context() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+        assert_eq!(
+            format_log_line(
+                LogFormat::Text,
+                time,
+                LogLevel::Info,
+                &test_context(),
+                "Starting a match"
+            ),
+            "2024-03-01 12:30:00.000 INFO    [10.0.0.5:51000] [request 12] Starting a match\n"
+        );
+    }
+
+    #[test]
+    fn test_json_log_line_is_one_object() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+        let line = format_log_line(
+            LogFormat::Json,
+            time,
+            LogLevel::Warning,
+            &test_context(),
+            "line\nbreak",
+        );
+        assert_eq!(line.matches('\n').count(), 1);
+        let object: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(object["level"], "warning");
+        assert_eq!(object["message"], "line\nbreak");
+        assert_eq!(object["peer_address"], "10.0.0.5:51000");
+        assert_eq!(object["request_id"], 12);
+    }
+
+    #[test]
+    fn test_log_file_is_rotated_when_it_is_too_big() {
+        let directory =
+            std::env::temp_dir().join(format!("log_rotation_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let path = directory.join("matchmaker.log");
+
+        let mut file = RotatingFile::open(path.clone(), Some(10), None, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(get_rotated_path(&path, 1)).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(get_rotated_path(&path, 2)).unwrap(),
+            "second\n"
+        );
+        assert!(!get_rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_log_level_is_parsed() {
+        assert_eq!(parse_log_level("debug"), Some(LogLevel::Debug));
+        assert_eq!(parse_log_level("verbose"), None);
+    }
+}
@@ -1,27 +1,179 @@
 use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{
+    collections::HashMap,
     fs,
     io::{prelude::*, BufReader},
     net::{TcpListener, TcpStream},
     os::unix,
+    os::unix::process::CommandExt,
     path::Path,
-    process::Command,
+    process::{Command, Stdio},
 };
 
+mod admin;
 mod arguments_parser;
+mod authentication;
 mod config;
 mod config_updaters;
+mod host_resources;
+mod instance_retention;
 mod json_file_updater;
+mod logging;
+mod matching;
+mod metrics;
+mod player_ratings;
+mod port_probe;
+mod port_reservations;
+mod protocol;
+mod request_error;
+mod server_callbacks;
+mod server_output;
+mod server_registry;
+mod sessions;
+mod thread_pool;
+mod tls;
+mod warm_pool;
 
 use rand::{distributions::Alphanumeric, Rng};
+use signal_hook::consts::{SIGINT, SIGTERM};
 
+use crate::authentication::Authenticator;
 use chrono::prelude::Utc;
-use crate::config::Config;
+use crate::config::{Config, GameModeConfig, ServerReadinessConfig, SharedConfig};
+use crate::instance_retention::InstanceRetention;
+use crate::logging::{log_debug, log_error, log_info, log_warning, LogContext};
+use crate::metrics::Metrics;
+use crate::player_ratings::PlayerRatings;
+use crate::port_reservations::PortReservations;
+use crate::protocol::{MatchTicket, Request, Response};
+use crate::request_error::RequestError;
+use crate::server_output::{ReadyFlag, ServerOutput};
+use crate::server_registry::{ServerId, ServerInstance, ServerRegistry, ServerState};
+use crate::sessions::{Session, SessionRegistry};
+use crate::warm_pool::WarmPool;
 
-const MATCHMAKER_PROTOCOL_VERSION: &str = "1";
+const MAINTENANCE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+const READINESS_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+const JOIN_TOKEN_LENGTH: usize = 32;
+const ACCEPT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+const SERVER_TERMINATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+// a rejected client gets this long to do the TLS handshake and take the response
+const REJECTED_CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+const REJECTED_CLIENT_MAX_INPUT_SIZE: usize = 64 * 1024;
+// connections that come over the limit are closed without a response once these threads are busy
+const REJECTION_THREADS_COUNT: usize = 4;
+// players with sessions can stay idle for a while between their requests, like between matches
+const SESSION_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+// an idle connection holds a connection thread, so the clients without sessions are closed sooner
+const ANONYMOUS_CLIENT_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+const CLIENT_ACTIVITY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+// idle client connections notice the shutdown within this time, so the matchmaker can exit
+const CLIENT_SHUTDOWN_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+// every request gets its own id to find all the log lines related to it
+static NEXT_REQUEST_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+// every connect request gets its own id to find its place in the queue
+static NEXT_WAIT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+struct WaitingPlayer {
+    // the connect request that waits for the match, changes when the player reconnects
+    wait_id: u64,
+    // set if the player identified itself with the hello request
+    player_id: Option<String>,
+    rating: Option<f32>,
+    queued_at: std::time::Instant,
+    // set while the player with a session has no connection to get the ticket
+    disconnected_at: Option<std::time::Instant>,
+    // the player gets the ticket to the server once the match is full,
+    // dropping the player without sending anything tells it that the server stopped
+    match_found_sender: std::sync::mpsc::Sender<Result<MatchTicket, RequestError>>,
+}
+
+/// Players that are going to play a match together, the match gets its dedicated server
+/// once it has all the players, so the players that wait for others don't hold a server
+struct MatchSlot {
+    // set once the match is complete
+    server_id: Option<ServerId>,
+    created_at: std::time::Instant,
+    players_waiting: Vec<WaitingPlayer>,
+    // how many times the server was replaced because it exited before the match was handed off
+    respawns_count: u32,
+    // set once a player waited too long, the match starts without waiting to be full
+    fill_with_bots: bool,
+}
+
+impl MatchSlot {
+    /// Whether the match can start once its server is ready
+    fn is_complete(&self, players_per_match: usize) -> bool {
+        self.players_waiting.len() >= players_per_match || self.fill_with_bots
+    }
+
+    fn get_match_candidate(&self) -> matching::MatchCandidate {
+        matching::MatchCandidate {
+            average_rating: matching::get_average_rating(
+                self.players_waiting.iter().map(|player| player.rating),
+            ),
+            players_count: self.players_waiting.len(),
+            waiting_time: self.created_at.elapsed(),
+        }
+    }
+}
 
 struct MatchmakerState {
-    players_waiting: Vec<String>,
+    // open match slots by game mode name
+    open_matches: HashMap<String, Vec<MatchSlot>>,
+    server_registry: ServerRegistry,
+    port_reservations: PortReservations,
+    is_shutting_down: bool,
+    // set from the admin interface, players can't join matches while it is set
+    is_matchmaking_paused: bool,
+    metrics: Metrics,
+    player_ratings: PlayerRatings,
+    // servers that are started in advance and wait for a match
+    warm_pool: WarmPool,
+    sessions: SessionRegistry,
+    authenticator: std::sync::Arc<dyn Authenticator>,
+    tls_certificates: Option<std::sync::Arc<tls::CertificateStore>>,
+}
+
+/// State of a client connection that is kept between its requests
+struct ClientConnection<'a> {
+    socket: &'a TcpStream,
+    protocol_version: u32,
+    session: Option<Session>,
+    // the connection keeps using the authenticator it started with, like the config
+    authenticator: std::sync::Arc<dyn Authenticator>,
+    // set if the next request was already read from the socket with the current one
+    has_buffered_input: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClientActivity {
+    Idle,
+    SentRequest,
+    Disconnected,
+}
+
+impl ClientConnection<'_> {
+    /// Checks without blocking whether the client sent something or closed the connection
+    /// while its request is being processed
+    fn check_activity(&self) -> ClientActivity {
+        if self.has_buffered_input {
+            return ClientActivity::SentRequest;
+        }
+        if self.socket.set_nonblocking(true).is_err() {
+            return ClientActivity::Idle;
+        }
+        let peek_result = self.socket.peek(&mut [0u8; 1]);
+        let _ = self.socket.set_nonblocking(false);
+        match peek_result {
+            Ok(0) => ClientActivity::Disconnected,
+            Ok(_) => ClientActivity::SentRequest,
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => ClientActivity::Idle,
+            Err(_) => ClientActivity::Disconnected,
+        }
+    }
 }
 
 struct ArgumentDescription {
@@ -30,7 +182,7 @@ struct ArgumentDescription {
     description: &'static str,
 }
 
-const ARGUMENTS: [ArgumentDescription; 3] = [
+const ARGUMENTS: [ArgumentDescription; 4] = [
     ArgumentDescription {
         name: "help",
         syntax: "help",
@@ -46,6 +198,11 @@ const ARGUMENTS: [ArgumentDescription; 3] = [
         syntax: "generate-default-config",
         description: "Generate default config file",
     },
+    ArgumentDescription {
+        name: "log-level",
+        syntax: "log-level <level>",
+        description: "Override the log level from the config (error, warning, info, debug)",
+    },
 ];
 
 fn main() {
@@ -81,24 +238,43 @@ fn main() {
         .get_value("config")
         .unwrap_or("data/config.json".to_string());
 
+    let log_level_override = match arguments.get_value("log-level") {
+        Some(value) => match logging::parse_log_level(&value) {
+            Some(log_level) => Some(log_level),
+            None => {
+                println!("Unknown log level: {}", value);
+                return;
+            }
+        },
+        None => None,
+    };
+
     let config = config::read_config(&config_path);
     let config = match config {
         Ok(config) => config,
         Err(error) => {
-            println!("Error reading config: {}.\nUse --generate-default-config to generate default config", error);
+            log_error!("Error reading config: {}.\nUse --generate-default-config to generate default config", error);
             return;
         }
     };
 
+    if let Err(error) = logging::init(&config.logging, log_level_override) {
+        log_error!("Problem setting up logging: {:?}", error);
+        return;
+    }
+
     // create the directory for the working directories
     fs::create_dir_all(&config.working_directiries_path).unwrap_or_else(|error| {
-        println!(
+        log_error!(
             "Problem creating directory '{}': {:?}",
-            config.working_directiries_path, error
+            config.working_directiries_path,
+            error
         );
     });
 
-    if !validate_dedicated_server_executable_path(&config) { return; }
+    if !validate_config(&config) {
+        return;
+    }
 
     let listener = TcpListener::bind(format!(
         "{}:{}",
@@ -113,44 +289,377 @@ fn main() {
         .ip()
         .to_string();
 
-    println!(
+    log_info!(
         "Matchmaker service started on inteface {} port {}",
-        interface, config.matchmaker_port
+        interface,
+        config.matchmaker_port
     );
 
+    let player_ratings = match &config.player_ratings.file_path {
+        Some(file_path) => match PlayerRatings::load(file_path) {
+            Ok(player_ratings) => player_ratings,
+            Err(error) => {
+                log_error!(
+                    "Problem reading player ratings from '{}': {}",
+                    file_path,
+                    error
+                );
+                return;
+            }
+        },
+        None => PlayerRatings::new(),
+    };
+
+    let authenticator = match authentication::create_authenticator(&config.authentication) {
+        Ok(authenticator) => authenticator,
+        Err(error) => {
+            log_error!("Problem setting up authentication: {}", error);
+            return;
+        }
+    };
+
+    let tls_certificates = match &config.tls {
+        Some(tls_config) => match tls::CertificateStore::load(tls_config) {
+            Ok(tls_certificates) => {
+                log_info!("Clients connect to the matchmaker with TLS");
+                Some(std::sync::Arc::new(tls_certificates))
+            }
+            Err(error) => {
+                log_error!("Problem loading TLS certificate: {}", error);
+                return;
+            }
+        },
+        None => None,
+    };
+    let tls_server_config = tls_certificates.clone().map(tls::create_server_config);
+
     let state = std::sync::Arc::new(std::sync::Mutex::new(MatchmakerState {
-        players_waiting: Vec::new(),
+        open_matches: HashMap::new(),
+        server_registry: ServerRegistry::new(),
+        port_reservations: PortReservations::new(
+            config.first_dedicated_server_port..=config.last_dedicated_server_port,
+        ),
+        is_shutting_down: false,
+        is_matchmaking_paused: false,
+        metrics: Metrics::default(),
+        player_ratings,
+        warm_pool: WarmPool::new(),
+        sessions: SessionRegistry::new(),
+        authenticator,
+        tls_certificates,
     }));
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
+    let instance_retention_path = config.working_directiries_path.clone();
+    let admin_address = config.admin_address.clone();
+    let metrics_address = config.metrics_address.clone();
+    let server_callback_address = config.server_callback_address.clone();
+    let connection_pool = thread_pool::ThreadPool::new(config.max_simultaneous_clients);
+    let rejection_pool = thread_pool::ThreadPool::new(REJECTION_THREADS_COUNT);
+    let config = std::sync::Arc::new(SharedConfig::new(config));
+
+    if let Some(admin_address) = &admin_address {
+        let admin_listener = match TcpListener::bind(admin_address) {
+            Ok(admin_listener) => admin_listener,
+            Err(error) => {
+                log_error!(
+                    "Problem starting admin interface on '{}': {:?}",
+                    admin_address,
+                    error
+                );
+                return;
+            }
+        };
+        log_info!("Admin interface started on {}", admin_address);
+        admin::start_admin_thread(admin_listener, state.clone(), config.clone(), config_path);
+    }
+
+    if let Some(metrics_address) = &metrics_address {
+        let metrics_listener = match TcpListener::bind(metrics_address) {
+            Ok(metrics_listener) => metrics_listener,
+            Err(error) => {
+                log_error!(
+                    "Problem starting metrics endpoint on '{}': {:?}",
+                    metrics_address,
+                    error
+                );
+                return;
+            }
+        };
+        log_info!("Metrics are served on http://{}/metrics", metrics_address);
+        metrics::start_metrics_thread(metrics_listener, state.clone());
+    }
 
-        handle_connection(
-            stream,
-            &config.working_directiries_path,
-            &config.dedicated_server_dir,
-            &interface,
+    if let Some(server_callback_address) = &server_callback_address {
+        let server_callback_listener = match TcpListener::bind(server_callback_address) {
+            Ok(server_callback_listener) => server_callback_listener,
+            Err(error) => {
+                log_error!(
+                    "Problem starting server callback endpoint on '{}': {:?}",
+                    server_callback_address,
+                    error
+                );
+                return;
+            }
+        };
+        log_info!(
+            "Dedicated servers report to the matchmaker on {}",
+            server_callback_address
+        );
+        server_callbacks::start_server_callback_thread(
+            server_callback_listener,
             state.clone(),
+            config.clone(),
+        );
+    }
+
+    // directories of the previous runs should be collected before any new server is started
+    let mut instance_retention = InstanceRetention::new();
+    instance_retention
+        .add_leftover_instances(&instance_retention_path, &config.get().instance_retention);
+
+    start_maintenance_thread(
+        state.clone(),
+        config.clone(),
+        interface.clone(),
+        instance_retention,
+    );
+
+    let shutdown_requested = std::sync::Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        // the second signal terminates the matchmaker without waiting for anything
+        signal_hook::flag::register_conditional_shutdown(signal, 1, shutdown_requested.clone())
+            .unwrap();
+        signal_hook::flag::register(signal, shutdown_requested.clone()).unwrap();
+    }
+
+    // the listener is polled to be able to notice the shutdown request
+    listener.set_nonblocking(true).unwrap();
+
+    let interface = std::sync::Arc::new(interface);
+    let mut shutdown_deadline: Option<std::time::Instant> = None;
+
+    loop {
+        if shutdown_deadline.is_none() && shutdown_requested.load(Ordering::Relaxed) {
+            log_info!("Shutting down, new matches are not accepted anymore");
+            begin_shutdown(&state);
+            shutdown_deadline = Some(
+                std::time::Instant::now()
+                    + std::time::Duration::from_secs(config.get().shutdown_timeout_seconds),
+            );
+        }
+
+        if let Some(shutdown_deadline) = shutdown_deadline {
+            let running_servers_count = state
+                .lock()
+                .unwrap()
+                .server_registry
+                .running_servers_count();
+            if running_servers_count == 0 || std::time::Instant::now() >= shutdown_deadline {
+                break;
+            }
+        }
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(error) = stream.set_nonblocking(false) {
+                    log_warning!("Problem setting up the connection: {:?}", error);
+                    continue;
+                }
+
+                // players waiting for a match keep their threads, so queueing the connection
+                // would leave the client without any response for as long as they wait
+                if !connection_pool.has_idle_worker() {
+                    log_warning!(
+                        "Rejecting a connection, all {} connection threads are busy",
+                        config.get().max_simultaneous_clients
+                    );
+                    state.lock().unwrap().metrics.rejected_connections_total += 1;
+                    // the TLS handshake can take a while, the listener shouldn't wait for it,
+                    // and a flood of connections shouldn't start a thread for each of them
+                    if rejection_pool.has_idle_worker() {
+                        let tls_server_config = tls_server_config.clone();
+                        rejection_pool
+                            .execute(move || reject_connection(stream, tls_server_config));
+                    }
+                    continue;
+                }
+
+                // the connection keeps using the config it started with
+                let config = config.get();
+                let interface = interface.clone();
+                let state = state.clone();
+                let tls_server_config = tls_server_config.clone();
+                let shutdown_requested = shutdown_requested.clone();
+                connection_pool.execute(move || {
+                    handle_connection(
+                        stream,
+                        tls_server_config,
+                        &config,
+                        &interface,
+                        state,
+                        &shutdown_requested,
+                    );
+                });
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(error) => {
+                log_error!("Problem accepting a connection: {:?}", error);
+            }
+        }
+    }
+
+    terminate_servers(&state);
+    log_info!("Matchmaker service stopped");
+}
+
+/// Stops matchmaking and lets the players that are waiting for a match know about it
+fn begin_shutdown(state: &std::sync::Mutex<MatchmakerState>) {
+    let mut state = state.lock().unwrap();
+    state.is_shutting_down = true;
+
+    let open_matches = std::mem::take(&mut state.open_matches);
+    for slot in open_matches.into_values().flatten() {
+        for player in slot.players_waiting {
+            let _ = player
+                .match_found_sender
+                .send(Err(RequestError::ShuttingDown));
+        }
+        // nobody is going to play on this server
+        if let Some(server_id) = slot.server_id {
+            state.server_registry.stop_server(server_id);
+        }
+    }
+    for server_id in state.warm_pool.take_all_servers() {
+        state.server_registry.stop_server(server_id);
+    }
+}
+
+/// Asks all the servers that are still running to stop, and kills the ones that don't stop in time
+fn terminate_servers(state: &std::sync::Mutex<MatchmakerState>) {
+    {
+        let mut state = state.lock().unwrap();
+        let server_ids = state.server_registry.get_server_ids();
+        if server_ids.is_empty() {
+            return;
+        }
+        log_info!("Stopping {} dedicated servers", server_ids.len());
+        for server_id in server_ids {
+            state.server_registry.stop_server(server_id);
+        }
+    }
+
+    let termination_deadline = std::time::Instant::now() + SERVER_TERMINATION_TIMEOUT;
+    while std::time::Instant::now() < termination_deadline {
+        let mut state = state.lock().unwrap();
+        reap_exited_servers(&mut state);
+        if state.server_registry.running_servers_count() == 0 {
+            return;
+        }
+        drop(state);
+        std::thread::sleep(ACCEPT_POLL_INTERVAL);
+    }
+
+    let mut state = state.lock().unwrap();
+    let server_ids = state.server_registry.get_server_ids();
+    log_warning!(
+        "Killing {} dedicated servers that didn't stop in time",
+        server_ids.len()
+    );
+    for server_id in server_ids {
+        state.server_registry.kill_server(server_id);
+    }
+}
+
+/// Checks the values that can't be checked when the config is parsed,
+/// used both on start and when the config is reloaded
+fn validate_config(config: &Config) -> bool {
+    if !validate_dedicated_server_executable_path(config) {
+        return false;
+    }
+
+    if !validate_game_modes(config) {
+        return false;
+    }
+
+    // the address is sent to the players in the tickets
+    let is_public_address_unspecified = config
+        .public_address
+        .parse::<std::net::IpAddr>()
+        .is_ok_and(|address| address.is_unspecified());
+    if config.public_address.is_empty() || is_public_address_unspecified {
+        log_error!("public_address should be set to the host name or IP address that players use to connect to the dedicated servers");
+        return false;
+    }
+
+    if config.first_dedicated_server_port > config.last_dedicated_server_port {
+        log_error!(
+            "first_dedicated_server_port should not be greater than last_dedicated_server_port"
         );
+        return false;
+    }
+
+    if config.max_simultaneous_clients == 0 {
+        log_error!("max_simultaneous_clients should be greater than zero");
+        return false;
+    }
+
+    if config.server_readiness == ServerReadinessConfig::Callback
+        && config.server_callback_address.is_none()
+    {
+        log_error!("server_callback_address should be set to use the callback server readiness");
+        return false;
+    }
+
+    if config.max_concurrent_servers == Some(0) {
+        log_error!("max_concurrent_servers should be greater than zero");
+        return false;
+    }
+
+    if config.max_queue_wait_seconds == Some(0) {
+        log_error!("max_queue_wait_seconds should be greater than zero");
+        return false;
+    }
+
+    if config.player_ratings.k_factor <= 0.0 {
+        log_error!("player_ratings.k_factor should be greater than zero");
+        return false;
     }
+
+    true
 }
 
 fn validate_dedicated_server_executable_path(config: &Config) -> bool {
     if Path::new(&config.dedicated_server_dir).is_absolute() {
         let path = Path::new(&config.dedicated_server_dir).join("DedicatedServer");
         if !path.is_file() {
-            println!(
+            log_error!(
                 "Dedicated server executable '{}' can't be found",
                 path.to_string_lossy()
             );
             return false;
         }
     } else {
+        // relative paths are resolved from the working directory of each server,
+        // which is created inside working_directiries_path
+        let path_from_working_directories = match Path::new(&config.dedicated_server_dir)
+            .strip_prefix("../")
+        {
+            Ok(path) => path,
+            Err(_) => {
+                log_error!(
+                        "Relative dedicated_server_dir '{}' should start with '../' to point outside of the server working directory",
+                        config.dedicated_server_dir
+                    );
+                return false;
+            }
+        };
         let path = Path::new(&config.working_directiries_path)
-            .join(Path::new(&config.dedicated_server_dir).strip_prefix("../").unwrap())
+            .join(path_from_working_directories)
             .join("DedicatedServer");
         if !path.is_file() {
-            println!(
+            log_error!(
                 "Dedicated server executable '{}' can't be found",
                 path.to_string_lossy()
             );
@@ -160,156 +669,1534 @@ fn validate_dedicated_server_executable_path(config: &Config) -> bool {
     true
 }
 
-fn start_dedicated_server(
-    port: u16,
-    dedicated_server_working_dir: &str,
-    dedicated_server_dir: &str,
-) -> Result<std::process::Child, std::io::Error> {
-    return Command::new("./run_detached_process.sh")
-        .arg(dedicated_server_working_dir)
-        .arg(format!("{}/DedicatedServer", dedicated_server_dir))
-        .arg(format!("--open-port {}", port))
-        .spawn();
+fn start_maintenance_thread(
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+    config: std::sync::Arc<SharedConfig>,
+    interface: String,
+    mut instance_retention: InstanceRetention,
+) {
+    let mut last_maintenance_time = std::time::Instant::now();
+    std::thread::spawn(move || loop {
+        // the readiness is checked more often than the rest to not keep the players waiting
+        std::thread::sleep(READINESS_CHECK_INTERVAL);
+        let config = config.get();
+
+        let is_maintenance_time = last_maintenance_time.elapsed() >= MAINTENANCE_INTERVAL;
+        let exited_servers = {
+            let mut state = state.lock().unwrap();
+            let exited_servers = reap_exited_servers(&mut state);
+            update_servers_readiness(&mut state, &config);
+            recover_failed_matches(&mut state, &config, &interface);
+            if is_maintenance_time && !state.is_shutting_down {
+                refill_warm_pools(&mut state, &config, &interface);
+            }
+            if is_maintenance_time {
+                remove_disconnected_players(&mut state, &config);
+                remove_stale_sessions(&mut state);
+                expire_waiting_players(&mut state, &config);
+                if !state.is_matchmaking_paused {
+                    merge_waiting_matches(&mut state, &config);
+                }
+                start_servers_for_complete_matches(&mut state, &config, &interface);
+            }
+            hand_off_ready_matches(&mut state, &config);
+            exited_servers
+        };
+        // the servers are reaped on every tick, so their directories are recorded right away
+        for server in exited_servers {
+            let is_crashed = matches!(server.state, ServerState::Crashed(_));
+            instance_retention.add_finished_instance(
+                &server.working_directory,
+                is_crashed,
+                &config.instance_retention,
+            );
+        }
+        if !is_maintenance_time {
+            continue;
+        }
+        last_maintenance_time = std::time::Instant::now();
+
+        // cleaning up can take a while, so it is done without blocking the matchmaking
+        instance_retention.apply_policy(&config.instance_retention);
+
+        // renewed certificates are picked up without a restart
+        let tls_certificates = state.lock().unwrap().tls_certificates.clone();
+        if let (Some(tls_certificates), Some(tls_config)) = (tls_certificates, &config.tls) {
+            match tls_certificates.reload_if_changed(tls_config) {
+                Ok(true) => log_info!(
+                    "TLS certificate is reloaded from '{}'",
+                    tls_config.certificate_path
+                ),
+                Ok(false) => {}
+                Err(error) => log_error!("Problem reloading TLS certificate: {}", error),
+            }
+        }
+    });
 }
 
-fn generate_unique_directory(working_dir: &str) -> String {
-    let random_string_part: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(7)
-        .map(char::from)
-        .collect();
+fn reap_exited_servers(state: &mut MatchmakerState) -> Vec<ServerInstance> {
+    let exited_servers = state.server_registry.reap_exited_servers();
+    for server in &exited_servers {
+        state.port_reservations.release_port(server.port);
+        if let Err(error) = server_output::write_exit_metadata(server) {
+            log_error!(
+                "Problem writing exit metadata of dedicated server {}: {:?}",
+                server.id,
+                error
+            );
+        }
 
-    // format: YYMMDD_HHMMSS_rand
-    let mut unique_name = Utc::now().format("%y%m%d_%H%M%S_").to_string() + &random_string_part;
-    while Path::new(working_dir).join(&unique_name).is_dir() {
-        let random_string_part: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(7)
-            .map(char::from)
-            .collect();
-        unique_name = Utc::now().format("%y%M%d_%H%m%s_").to_string() + &random_string_part;
+        let description = format!(
+            "Dedicated server {} on port {} (pid {}, started at {}, directory '{}')",
+            server.id,
+            server.port,
+            server.pid,
+            server.start_time.format("%Y-%m-%d %H:%M:%S"),
+            server.working_directory
+        );
+        if let ServerState::Crashed(_) = server.state {
+            state.metrics.server_crashes_total += 1;
+        }
+        match server.state {
+            ServerState::Crashed(status) => log_warning!("{} crashed: {}", description, status),
+            ServerState::Stopped => log_info!("{} stopped", description),
+            _ => log_info!("{} finished", description),
+        }
+    }
+    if !exited_servers.is_empty() {
+        log_info!(
+            "{} dedicated servers are still running",
+            state.server_registry.running_servers_count()
+        );
     }
+    exited_servers
+}
 
-    return Path::new(working_dir)
-        .join(&unique_name)
-        .to_string_lossy()
-        .to_string();
+/// Checks whether the servers that are starting are ready to accept players,
+/// and kills the ones that didn't get ready within the startup timeout
+fn update_servers_readiness(state: &mut MatchmakerState, config: &Config) {
+    let startup_timeout = std::time::Duration::from_secs(config.server_startup_timeout_seconds);
+
+    for server_id in state.server_registry.get_server_ids() {
+        let server = state.server_registry.get(server_id).unwrap();
+        if server.is_ready || server.state != ServerState::Running {
+            continue;
+        }
+
+        let is_ready = match &config.server_readiness {
+            ServerReadinessConfig::None => true,
+            ServerReadinessConfig::UdpPort => port_probe::is_udp_port_bound(server.port),
+            ServerReadinessConfig::StdoutLine { .. } => match &server.ready_flag {
+                Some(ready_flag) => ready_flag.load(Ordering::Relaxed),
+                // the server was started before the readiness method was changed
+                None => true,
+            },
+            // the server is marked ready when it reports that
+            ServerReadinessConfig::Callback => false,
+        };
+        if is_ready {
+            log_debug!("Dedicated server {} is ready", server_id);
+            state.server_registry.mark_ready(server_id);
+            continue;
+        }
+
+        let running_time = Utc::now()
+            .signed_duration_since(server.start_time)
+            .to_std()
+            .unwrap_or_default();
+        if running_time >= startup_timeout {
+            log_warning!(
+                "Dedicated server {} on port {} didn't get ready in {} seconds, killing it",
+                server_id,
+                server.port,
+                config.server_startup_timeout_seconds
+            );
+            state.metrics.server_startup_timeouts_total += 1;
+            state.server_registry.kill_server(server_id);
+        }
+    }
 }
 
-fn create_dedicated_server_environment(
-    dedicated_server_working_dir: &str,
-    dedicated_server_dir: &str,
-) {
-    fs::create_dir_all(dedicated_server_working_dir).unwrap_or_else(|error| {
-        println!(
-            "Problem creating directory '{}': {:?}",
-            dedicated_server_working_dir, error
-        );
-    });
-    unix::fs::symlink(
-        format!("{}/resources", dedicated_server_dir),
-        Path::new(dedicated_server_working_dir).join("resources"),
-    )
-    .unwrap();
+/// Replaces the servers of the matches that are not handed off yet if the servers exited
+/// or were killed, so the players that are already waiting don't lose their place
+fn recover_failed_matches(state: &mut MatchmakerState, config: &Config, interface: &str) {
+    let state = &mut *state;
+    for (game_mode_name, slots) in state.open_matches.iter_mut() {
+        let game_mode = match config.get_game_mode(Some(game_mode_name)) {
+            Some(game_mode) => game_mode,
+            None => continue,
+        };
+
+        slots.retain_mut(|slot| {
+            let server_id = match slot.server_id {
+                Some(server_id) => server_id,
+                None => return true,
+            };
+            if state.server_registry.is_running(server_id) {
+                return true;
+            }
+
+            state.metrics.server_early_exits_total += 1;
+            if slot.players_waiting.is_empty() {
+                return false;
+            }
+            if slot.respawns_count >= config.max_server_respawns {
+                log_warning!(
+                    "Dedicated server {} stopped before its match was handed off and was already replaced {} times, giving up on the match",
+                    server_id,
+                    slot.respawns_count
+                );
+                return false;
+            }
+
+            match get_server_for_new_match(
+                config,
+                game_mode,
+                interface,
+                &mut state.warm_pool,
+                &mut state.server_registry,
+                &mut state.port_reservations,
+                &mut state.metrics,
+            ) {
+                Ok(new_server_id) => {
+                    log_info!(
+                        "Dedicated server {} stopped before its match was handed off, replaced it with server {} for {} waiting players",
+                        server_id,
+                        new_server_id,
+                        slot.players_waiting.len()
+                    );
+                    state.metrics.server_respawns_total += 1;
+                    slot.server_id = Some(new_server_id);
+                    slot.respawns_count += 1;
+                    true
+                }
+                Err(error) => {
+                    log_error!(
+                        "Problem replacing dedicated server {}: {}",
+                        server_id,
+                        error
+                    );
+                    false
+                }
+            }
+        });
+    }
 }
 
-fn start_new_server(
-    working_directories_path: &str,
-    dedicated_server_dir: &str,
-    interface: &str,
-) -> Option<String> {
-    let port: Option<u16> = get_available_port(interface);
-    match port {
-        Some(val) => {
-            let new_server_working_dir = generate_unique_directory(working_directories_path);
-            create_dedicated_server_environment(&new_server_working_dir, dedicated_server_dir);
-            match start_dedicated_server(val, &new_server_working_dir, dedicated_server_dir) {
-                Ok(_) => {
-                    println!("Spawned new dedicated server on port {}", val);
-                    return Some(val.to_string());
+/// Keeps the configured number of idle servers for every game mode,
+/// servers that were idle for too long are replaced with fresh ones
+fn refill_warm_pools(state: &mut MatchmakerState, config: &Config, interface: &str) {
+    let server_registry = &state.server_registry;
+    state
+        .warm_pool
+        .remove_stopped_servers(|server_id| server_registry.is_running(server_id));
+
+    let mut servers_to_stop = state.warm_pool.take_surplus_servers(|game_mode_name| {
+        config
+            .get_game_mode(Some(game_mode_name))
+            .map_or(0, |game_mode| game_mode.warm_servers_count)
+    });
+    if let Some(idle_timeout_seconds) = config.warm_server_idle_timeout_seconds {
+        servers_to_stop.extend(
+            state
+                .warm_pool
+                .take_expired_servers(std::time::Duration::from_secs(idle_timeout_seconds)),
+        );
+    }
+    for server_id in servers_to_stop {
+        log_info!("Stopping idle warm dedicated server {}", server_id);
+        state.server_registry.stop_server(server_id);
+    }
+
+    for game_mode in &config.game_modes {
+        while state.warm_pool.get_idle_servers_count(&game_mode.name) < game_mode.warm_servers_count
+        {
+            // the capacity that is left is kept for the matches that players are waiting for
+            if check_server_capacity(config, &state.server_registry).is_err() {
+                return;
+            }
+            match start_new_server(
+                config,
+                game_mode,
+                interface,
+                &mut state.server_registry,
+                &mut state.port_reservations,
+                &mut state.metrics,
+            ) {
+                Ok(server_id) => state.warm_pool.add_server(&game_mode.name, server_id),
+                Err(error) => {
+                    log_error!(
+                        "Problem starting warm dedicated server for '{}': {}",
+                        game_mode.name,
+                        error
+                    );
+                    // trying again right away would most likely fail the same way
+                    break;
                 }
-                Err(error) => return Some(format!("Problem opening the file: {:?}", error)),
             }
         }
-        None => return Some("no ports".to_string()),
     }
 }
 
-fn process_one_line_request(
-    request: &String,
-    working_directories_path: &str,
-    dedicated_server_dir: &str,
-    interface: &str,
-    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+/// Returns the game mode that the player is waiting for a match of
+fn find_queued_game_mode(
+    open_matches: &HashMap<String, Vec<MatchSlot>>,
+    player_id: &str,
 ) -> Option<String> {
-    if request == "protocol-version" {
-        return Some(MATCHMAKER_PROTOCOL_VERSION.to_string());
-    }
-    if request == "connect" {
-        let mut state = state.lock().unwrap();
-        if state.players_waiting.len() > 0 {
-            let port = state.players_waiting.pop().unwrap();
-            return Some(format!("port:{}", &port));
-        } else {
-            state.players_waiting.push(
-                start_new_server(working_directories_path, dedicated_server_dir, interface)
-                    .unwrap(),
-            );
-            return Some(format!("port:{}", &state.players_waiting[0]));
+    open_matches
+        .iter()
+        .find(|(_, slots)| {
+            slots
+                .iter()
+                .flat_map(|slot| slot.players_waiting.iter())
+                .any(|player| player.player_id.as_deref() == Some(player_id))
+        })
+        .map(|(game_mode_name, _)| game_mode_name.clone())
+}
+
+fn remove_stale_sessions(state: &mut MatchmakerState) {
+    let open_matches = &state.open_matches;
+    state.sessions.remove_stale_sessions(|player_id| {
+        find_queued_game_mode(open_matches, player_id).is_some()
+    });
+}
+
+/// Moves the place of the player in the queue to the new connection of the player,
+/// returns false if the player is not waiting for a match in these slots
+fn reattach_waiting_player(
+    slots: &mut [MatchSlot],
+    player_id: &str,
+    wait_id: u64,
+    match_found_sender: &std::sync::mpsc::Sender<Result<MatchTicket, RequestError>>,
+) -> bool {
+    let player = match slots
+        .iter_mut()
+        .flat_map(|slot| slot.players_waiting.iter_mut())
+        .find(|player| player.player_id.as_deref() == Some(player_id))
+    {
+        Some(player) => player,
+        None => return false,
+    };
+
+    // the previous connection of the player stops waiting
+    let previous_sender =
+        std::mem::replace(&mut player.match_found_sender, match_found_sender.clone());
+    let _ = previous_sender.send(Err(RequestError::SessionReplaced));
+    player.wait_id = wait_id;
+    player.disconnected_at = None;
+    true
+}
+
+fn find_waiting_player(
+    open_matches: &mut HashMap<String, Vec<MatchSlot>>,
+    wait_id: u64,
+) -> Option<&mut WaitingPlayer> {
+    open_matches
+        .values_mut()
+        .flatten()
+        .flat_map(|slot| slot.players_waiting.iter_mut())
+        .find(|player| player.wait_id == wait_id)
+}
+
+/// Takes the first matching player out of the queue, the server of a match that nobody waits for
+/// anymore is released
+fn remove_waiting_player<F>(
+    state: &mut MatchmakerState,
+    config: &Config,
+    is_player: F,
+) -> Option<WaitingPlayer>
+where
+    F: Fn(&WaitingPlayer) -> bool,
+{
+    for (game_mode_name, slots) in state.open_matches.iter_mut() {
+        for slot_index in 0..slots.len() {
+            let player_index = match slots[slot_index]
+                .players_waiting
+                .iter()
+                .position(&is_player)
+            {
+                Some(player_index) => player_index,
+                None => continue,
+            };
+            let player = slots[slot_index].players_waiting.remove(player_index);
+            if !slots[slot_index].players_waiting.is_empty() {
+                return Some(player);
+            }
+
+            if let Some(server_id) = slots.remove(slot_index).server_id {
+                release_unused_server(
+                    config,
+                    game_mode_name,
+                    server_id,
+                    &mut state.warm_pool,
+                    &mut state.server_registry,
+                );
+            }
+            return Some(player);
         }
-    } else {
-        println!("Unknown one line request: {:#?}", request);
-        return None;
     }
+    None
 }
 
-fn handle_connection(
-    mut stream: TcpStream,
-    working_directories_path: &str,
-    dedicated_server_dir: &str,
-    interface: &str,
-    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+/// The server of a match that is not going to be played on it goes to the warm pool if the pool
+/// of its game mode is not full, otherwise it is stopped
+fn release_unused_server(
+    config: &Config,
+    game_mode_name: &str,
+    server_id: ServerId,
+    warm_pool: &mut WarmPool,
+    server_registry: &mut ServerRegistry,
 ) {
-    while let Ok(_) = stream.set_read_timeout(Some(std::time::Duration::from_millis(100))) {
-        let mut reader = BufReader::new(&stream);
-        let mut http_request = String::new();
-        let read_result = reader.read_line(&mut http_request);
-        if read_result.is_err() {
-            break;
+    // nobody got the join token of this server yet, so it is as good as a fresh one
+    let is_needed_in_warm_pool =
+        config
+            .get_game_mode(Some(game_mode_name))
+            .is_some_and(|game_mode| {
+                warm_pool.get_idle_servers_count(game_mode_name) < game_mode.warm_servers_count
+            });
+    if is_needed_in_warm_pool {
+        log_info!(
+            "Dedicated server {} is not needed for its match anymore, it goes to the warm pool",
+            server_id
+        );
+        warm_pool.add_server(game_mode_name, server_id);
+    } else {
+        log_info!(
+            "Dedicated server {} is not needed for its match anymore, stopping it",
+            server_id
+        );
+        server_registry.stop_server(server_id);
+    }
+}
+
+/// Removes the players with sessions that lost their connections and didn't come back in time
+fn remove_disconnected_players(state: &mut MatchmakerState, config: &Config) {
+    let reconnect_timeout =
+        std::time::Duration::from_secs(config.session_reconnect_timeout_seconds);
+    while let Some(player) = remove_waiting_player(state, config, |player| {
+        player
+            .disconnected_at
+            .is_some_and(|disconnected_at| disconnected_at.elapsed() >= reconnect_timeout)
+    }) {
+        log_info!(
+            "Player '{}' didn't reconnect in time and left the queue",
+            player.player_id.unwrap_or_default()
+        );
+    }
+}
+
+/// Tells the players that waited longer than the maximum queue wait that no match was found.
+/// In the game modes that are filled with bots their matches start with the players they have.
+fn expire_waiting_players(state: &mut MatchmakerState, config: &Config) {
+    let max_queue_wait = match config.max_queue_wait_seconds {
+        Some(max_queue_wait_seconds) => std::time::Duration::from_secs(max_queue_wait_seconds),
+        None => return,
+    };
+    let is_expired = |player: &WaitingPlayer| player.queued_at.elapsed() >= max_queue_wait;
+
+    let mut expired_wait_ids = Vec::new();
+    for (game_mode_name, slots) in state.open_matches.iter_mut() {
+        let fill_with_bots = config
+            .get_game_mode(Some(game_mode_name))
+            .is_some_and(|game_mode| game_mode.fill_with_bots);
+        for slot in slots.iter_mut() {
+            if !fill_with_bots {
+                expired_wait_ids.extend(
+                    slot.players_waiting
+                        .iter()
+                        .filter(|player| is_expired(player))
+                        .map(|player| player.wait_id),
+                );
+            } else if !slot.fill_with_bots && slot.players_waiting.iter().any(is_expired) {
+                log_info!(
+                    "Not enough players joined a '{}' match in time, the missing players are replaced with bots",
+                    game_mode_name
+                );
+                slot.fill_with_bots = true;
+            }
         }
-        let read_result = read_result.unwrap();
-        if read_result == 0 {
-            break;
+    }
+
+    for wait_id in expired_wait_ids {
+        if let Some(player) =
+            remove_waiting_player(state, config, |player| player.wait_id == wait_id)
+        {
+            state.metrics.queue_timeouts_total += 1;
+            let _ = player
+                .match_found_sender
+                .send(Err(RequestError::QueueTimeout));
         }
-        let http_request: Vec<String> = http_request
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect();
+    }
+}
 
-        if http_request.len() == 1 {
-            let response = process_one_line_request(
-                &http_request[0],
-                working_directories_path,
-                dedicated_server_dir,
-                interface,
-                state.clone(),
-            );
+fn get_rating_window_settings(config: &Config) -> matching::RatingWindowSettings {
+    matching::RatingWindowSettings {
+        initial_rating_window: config.initial_rating_window,
+        rating_window_growth_per_second: config.rating_window_growth_per_second,
+    }
+}
+
+/// Combines players of the matches that wait long enough for their rating windows to overlap
+fn merge_waiting_matches(state: &mut MatchmakerState, config: &Config) {
+    let rating_window_settings = get_rating_window_settings(config);
 
-            match response {
-                Some(val) => {
-                    println!("Responding with: {}", val);
-                    stream.write_all(val.as_bytes()).unwrap()
+    for game_mode in &config.game_modes {
+        let slots = match state.open_matches.get_mut(&game_mode.name) {
+            Some(slots) => slots,
+            None => continue,
+        };
+
+        loop {
+            let candidates: Vec<matching::MatchCandidate> =
+                slots.iter().map(MatchSlot::get_match_candidate).collect();
+            let (target_index, source_index) = match matching::find_matches_to_merge(
+                &candidates,
+                game_mode.players_per_match,
+                &rating_window_settings,
+            ) {
+                Some(indices) => indices,
+                None => break,
+            };
+
+            let source_slot = slots.remove(source_index);
+            let target_index = if source_index < target_index {
+                target_index - 1
+            } else {
+                target_index
+            };
+
+            // the players are moved to the other match, its server is used if it has one
+            if let Some(server_id) = source_slot.server_id {
+                match slots[target_index].server_id {
+                    Some(_) => release_unused_server(
+                        config,
+                        &game_mode.name,
+                        server_id,
+                        &mut state.warm_pool,
+                        &mut state.server_registry,
+                    ),
+                    None => slots[target_index].server_id = Some(server_id),
                 }
-                None => {}
             }
-        } else {
-            println!("Unknown request: {:#?}", http_request);
+            slots[target_index]
+                .players_waiting
+                .extend(source_slot.players_waiting);
         }
     }
 }
 
-fn get_available_port(interface: &str) -> Option<u16> {
-    (8000..9000).find(|port| is_port_available(interface, *port))
+/// Gives dedicated servers to the matches that got all their players or are going to be
+/// filled with bots, the matches that couldn't get one try again on the next maintenance
+fn start_servers_for_complete_matches(
+    state: &mut MatchmakerState,
+    config: &Config,
+    interface: &str,
+) {
+    for game_mode in &config.game_modes {
+        let slots = match state.open_matches.get_mut(&game_mode.name) {
+            Some(slots) => slots,
+            None => continue,
+        };
+        for slot in slots.iter_mut() {
+            if slot.server_id.is_some() || !slot.is_complete(game_mode.players_per_match) {
+                continue;
+            }
+            match get_server_for_new_match(
+                config,
+                game_mode,
+                interface,
+                &mut state.warm_pool,
+                &mut state.server_registry,
+                &mut state.port_reservations,
+                &mut state.metrics,
+            ) {
+                Ok(server_id) => slot.server_id = Some(server_id),
+                Err(error) => {
+                    log_warning!(
+                        "Problem starting dedicated server for a '{}' match: {}",
+                        game_mode.name,
+                        error
+                    );
+                    // the other matches would most likely fail the same way
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Sends the tickets to the players of the matches that are full and which servers are ready,
+/// the full matches which servers are still starting stay in the queue until the servers are ready
+fn hand_off_ready_matches(state: &mut MatchmakerState, config: &Config) {
+    for (game_mode_name, slots) in state.open_matches.iter_mut() {
+        let game_mode = match config.get_game_mode(Some(game_mode_name)) {
+            Some(game_mode) => game_mode,
+            None => continue,
+        };
+
+        let (ready_slots, waiting_slots): (Vec<MatchSlot>, Vec<MatchSlot>) =
+            std::mem::take(slots).into_iter().partition(|slot| {
+                // players that lost their connections can't get the ticket,
+                // the match waits for them to come back or to leave the queue
+                slot.is_complete(game_mode.players_per_match)
+                    && slot
+                        .players_waiting
+                        .iter()
+                        .all(|player| player.disconnected_at.is_none())
+                    && slot
+                        .server_id
+                        .is_some_and(|server_id| state.server_registry.is_ready(server_id))
+            });
+        *slots = waiting_slots;
+        for slot in ready_slots {
+            hand_off_match(
+                slot,
+                game_mode,
+                &mut state.server_registry,
+                &mut state.metrics,
+                config,
+            );
+        }
+    }
+}
+
+fn hand_off_match(
+    slot: MatchSlot,
+    game_mode: &GameModeConfig,
+    server_registry: &mut ServerRegistry,
+    metrics: &mut Metrics,
+    config: &Config,
+) {
+    // only the slots with ready servers are handed off
+    let server = server_registry.get_mut(slot.server_id.unwrap()).unwrap();
+    // the server can report the results only for the players that were sent to it
+    server.matched_players.extend(
+        slot.players_waiting
+            .iter()
+            .filter_map(|player| player.player_id.clone()),
+    );
+    let players_count = slot.players_waiting.len();
+    let bots_count = if slot.fill_with_bots {
+        game_mode.players_per_match.saturating_sub(players_count)
+    } else {
+        0
+    };
+    if bots_count > 0 {
+        log_info!(
+            "Starting a match of {} players and {} bots on port {}",
+            players_count,
+            bots_count,
+            server.port
+        );
+        metrics.bot_filled_matches_total += 1;
+    } else {
+        log_info!(
+            "Starting a match of {} players on port {}",
+            players_count,
+            server.port
+        );
+    }
+
+    let match_info = server_output::MatchInfo {
+        players_count,
+        fill_with_bots: bots_count > 0,
+        bots_count,
+    };
+    if let Err(error) = server_output::write_match_info(&server.working_directory, &match_info) {
+        log_error!(
+            "Problem writing match info of dedicated server {}: {:?}",
+            server.id,
+            error
+        );
+    }
+
+    let ticket = MatchTicket {
+        host: config.public_address.clone(),
+        port: server.port,
+        token: server.join_token.clone(),
+        fill_with_bots: bots_count > 0,
+    };
+    metrics.matches_created_total += 1;
+    for player in slot.players_waiting {
+        metrics.add_match_wait_time(player.queued_at.elapsed());
+        // the player may have disconnected already, nothing to do in this case
+        let _ = player.match_found_sender.send(Ok(ticket.clone()));
+    }
+}
+
+fn validate_game_modes(config: &Config) -> bool {
+    if config.game_modes.is_empty() {
+        log_error!("At least one game mode should be configured");
+        return false;
+    }
+    for (index, game_mode) in config.game_modes.iter().enumerate() {
+        if config.game_modes[..index]
+            .iter()
+            .any(|other| other.name == game_mode.name)
+        {
+            log_error!(
+                "Game mode '{}' is configured more than once",
+                game_mode.name
+            );
+            return false;
+        }
+        if game_mode.players_per_match == 0 {
+            log_error!(
+                "players_per_match of game mode '{}' should be greater than zero",
+                game_mode.name
+            );
+            return false;
+        }
+    }
+    true
+}
+
+fn start_dedicated_server(
+    port: u16,
+    dedicated_server_working_dir: &str,
+    config: &Config,
+    game_mode: &GameModeConfig,
+    join_token: &str,
+    server_token: &str,
+) -> Result<(std::process::Child, Option<ReadyFlag>), std::io::Error> {
+    // relative paths to the dedicated server are relative to its working directory,
+    // the path is made absolute so the child doesn't resolve it against that directory again
+    let executable_path = fs::canonicalize(
+        Path::new(dedicated_server_working_dir)
+            .join(&config.dedicated_server_dir)
+            .join("DedicatedServer"),
+    )?;
+
+    let ready_line = match &config.server_readiness {
+        ServerReadinessConfig::StdoutLine { line } => Some(line.as_str()),
+        _ => None,
+    };
+    let server_output = ServerOutput::create(
+        dedicated_server_working_dir,
+        config.max_server_output_size_bytes,
+        ready_line,
+    )?;
+    let (stdout, stderr) = server_output.get_stdio()?;
+
+    // the join token is given to the players, so the server identifies itself with its own token
+    let callback_arguments = match &config.server_callback_address {
+        Some(address) => vec![
+            "--matchmaker-address",
+            address.as_str(),
+            "--server-token",
+            server_token,
+        ],
+        None => Vec::new(),
+    };
+
+    let mut process = Command::new(executable_path)
+        .current_dir(dedicated_server_working_dir)
+        .arg("--open-port")
+        .arg(port.to_string())
+        .arg("--join-token")
+        .arg(join_token)
+        .args(&callback_arguments)
+        .args(&game_mode.dedicated_server_arguments)
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr)
+        // keep the server in its own process group so signals sent to the matchmaker
+        // from the terminal don't bring down running matches
+        .process_group(0)
+        .spawn()?;
+
+    let ready_flag = server_output.attach(&mut process);
+    Ok((process, ready_flag))
+}
+
+fn generate_random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn generate_unique_directory(working_dir: &str) -> String {
+    let random_string_part = generate_random_string(7);
+
+    // format: YYMMDD_HHMMSS_rand
+    let mut unique_name = Utc::now().format("%y%m%d_%H%M%S_").to_string() + &random_string_part;
+    while Path::new(working_dir).join(&unique_name).is_dir() {
+        let random_string_part = generate_random_string(7);
+        unique_name = Utc::now().format("%y%m%d_%H%M%S_").to_string() + &random_string_part;
+    }
+
+    return Path::new(working_dir)
+        .join(&unique_name)
+        .to_string_lossy()
+        .to_string();
+}
+
+fn create_dedicated_server_environment(
+    dedicated_server_working_dir: &str,
+    dedicated_server_dir: &str,
+    resources_dir: &str,
+) -> Result<(), std::io::Error> {
+    fs::create_dir_all(dedicated_server_working_dir)?;
+    unix::fs::symlink(
+        Path::new(dedicated_server_dir).join(resources_dir),
+        Path::new(dedicated_server_working_dir).join("resources"),
+    )
+}
+
+/// Takes a server from the warm pool if there is one, otherwise starts a new one
+fn get_server_for_new_match(
+    config: &Config,
+    game_mode: &GameModeConfig,
+    interface: &str,
+    warm_pool: &mut WarmPool,
+    server_registry: &mut ServerRegistry,
+    port_reservations: &mut PortReservations,
+    metrics: &mut Metrics,
+) -> Result<ServerId, RequestError> {
+    warm_pool.remove_stopped_servers(|server_id| server_registry.is_running(server_id));
+    match warm_pool.take_server(&game_mode.name, |server_id| {
+        server_registry.is_ready(server_id)
+    }) {
+        Some(server_id) => {
+            log_info!(
+                "Using warm dedicated server {} for a new '{}' match",
+                server_id,
+                game_mode.name
+            );
+            metrics.warm_servers_used_total += 1;
+            Ok(server_id)
+        }
+        None => start_new_server(
+            config,
+            game_mode,
+            interface,
+            server_registry,
+            port_reservations,
+            metrics,
+        ),
+    }
+}
+
+/// Returns why a new server can't be started if any of the capacity limits is reached
+fn check_server_capacity(config: &Config, server_registry: &ServerRegistry) -> Result<(), String> {
+    if let Some(max_concurrent_servers) = config.max_concurrent_servers {
+        let running_servers_count = server_registry.running_servers_count();
+        if running_servers_count >= max_concurrent_servers {
+            return Err(format!(
+                "{} dedicated servers are running, the limit is {}",
+                running_servers_count, max_concurrent_servers
+            ));
+        }
+    }
+
+    let admission_control = &config.admission_control;
+    if let Some(max_load_average_per_cpu) = admission_control.max_load_average_per_cpu {
+        if let Some(load_average_per_cpu) = host_resources::get_load_average_per_cpu() {
+            if load_average_per_cpu > max_load_average_per_cpu {
+                return Err(format!(
+                    "load average per CPU is {:.2}, the limit is {:.2}",
+                    load_average_per_cpu, max_load_average_per_cpu
+                ));
+            }
+        }
+    }
+    if let Some(min_available_memory_bytes) = admission_control.min_available_memory_bytes {
+        if let Some(available_memory_bytes) = host_resources::get_available_memory_bytes() {
+            if available_memory_bytes < min_available_memory_bytes {
+                return Err(format!(
+                    "{} bytes of memory are available, at least {} are required",
+                    available_memory_bytes, min_available_memory_bytes
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn start_new_server(
+    config: &Config,
+    game_mode: &GameModeConfig,
+    interface: &str,
+    server_registry: &mut ServerRegistry,
+    port_reservations: &mut PortReservations,
+    metrics: &mut Metrics,
+) -> Result<ServerId, RequestError> {
+    if let Err(reason) = check_server_capacity(config, server_registry) {
+        log_warning!("Not starting a new dedicated server: {}", reason);
+        metrics.server_capacity_rejections_total += 1;
+        return Err(RequestError::ServerFull {
+            retry_after_seconds: config.server_full_retry_after_seconds,
+        });
+    }
+
+    let port: Option<u16> = get_available_port(interface, port_reservations, metrics);
+    match port {
+        Some(val) => {
+            let new_server_working_dir =
+                generate_unique_directory(&config.working_directiries_path);
+            if let Err(error) = create_dedicated_server_environment(
+                &new_server_working_dir,
+                &config.dedicated_server_dir,
+                &game_mode.resources_dir,
+            ) {
+                log_error!(
+                    "Problem creating dedicated server directory '{}': {:?}",
+                    new_server_working_dir,
+                    error
+                );
+                port_reservations.release_port(val);
+                metrics.server_spawn_failures_total += 1;
+                return Err(RequestError::ServerEnvironmentFailed(error));
+            }
+            let join_token = generate_random_string(JOIN_TOKEN_LENGTH);
+            let server_token = generate_random_string(JOIN_TOKEN_LENGTH);
+            match start_dedicated_server(
+                val,
+                &new_server_working_dir,
+                config,
+                game_mode,
+                &join_token,
+                &server_token,
+            ) {
+                Ok((process, ready_flag)) => {
+                    log_info!(
+                        "Spawned new '{}' dedicated server on port {} (pid {})",
+                        game_mode.name,
+                        val,
+                        process.id()
+                    );
+                    let server_id = server_registry.register(
+                        process,
+                        val,
+                        join_token,
+                        server_token,
+                        new_server_working_dir,
+                        ready_flag,
+                    );
+                    if config.server_readiness == ServerReadinessConfig::None {
+                        server_registry.mark_ready(server_id);
+                    }
+                    Ok(server_id)
+                }
+                Err(error) => {
+                    log_error!("Problem starting dedicated server: {:?}", error);
+                    port_reservations.release_port(val);
+                    metrics.server_spawn_failures_total += 1;
+                    Err(RequestError::ServerSpawnFailed(error))
+                }
+            }
+        }
+        None => Err(RequestError::NoPortsAvailable),
+    }
+}
+
+fn process_request(
+    request: Request,
+    connection: &mut ClientConnection,
+    config: &Config,
+    interface: &str,
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+) -> Result<Response, RequestError> {
+    match request {
+        Request::ProtocolVersion { version: None } => Ok(Response::ProtocolVersion {
+            version: protocol::LEGACY_PROTOCOL_VERSION,
+        }),
+        Request::ProtocolVersion {
+            version: Some(version),
+        } => match protocol::negotiate_protocol_version(version) {
+            Some(version) => {
+                connection.protocol_version = version;
+                Ok(Response::ProtocolVersion { version })
+            }
+            None => Err(RequestError::UnsupportedProtocolVersion),
+        },
+        Request::Hello { player_id, auth } => {
+            process_hello_request(&player_id, &auth, connection, state)
+        }
+        Request::Connect { game_mode, rating } => process_connect_request(
+            game_mode.as_deref(),
+            rating,
+            connection,
+            config,
+            interface,
+            state,
+        ),
+        Request::Cancel => process_cancel_request(connection, config, state),
+    }
+}
+
+fn process_hello_request(
+    player_id: &str,
+    auth: &str,
+    connection: &mut ClientConnection,
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+) -> Result<Response, RequestError> {
+    if connection.session.is_some() {
+        return Err(RequestError::InvalidRequest(
+            "the connection already has a session".to_string(),
+        ));
+    }
+    sessions::validate_player_id(player_id)?;
+
+    if let Err(reason) = connection.authenticator.authenticate(player_id, auth) {
+        log_warning!("Player '{}' failed to authenticate: {}", player_id, reason);
+        state.lock().unwrap().metrics.authentication_failures_total += 1;
+        return Err(RequestError::AuthenticationFailed);
+    }
+
+    let mut state = state.lock().unwrap();
+    let state = &mut *state;
+    let queued_game_mode = find_queued_game_mode(&state.open_matches, player_id);
+    let new_session = state.sessions.open_session(
+        player_id,
+        auth,
+        queued_game_mode.is_some(),
+        connection.authenticator.is_identity_verified(),
+    )?;
+    log_info!(
+        "Player '{}' started session {}",
+        new_session.player_id,
+        new_session.id
+    );
+
+    let response = Response::SessionStarted {
+        session_id: new_session.id,
+        queued_game_mode,
+    };
+    connection.session = Some(new_session);
+    Ok(response)
+}
+
+fn process_connect_request(
+    game_mode_name: Option<&str>,
+    rating: Option<f32>,
+    connection: &ClientConnection,
+    config: &Config,
+    interface: &str,
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+) -> Result<Response, RequestError> {
+    let game_mode = match config.get_game_mode(game_mode_name) {
+        Some(game_mode) => game_mode,
+        None => {
+            return Err(RequestError::UnknownGameMode(
+                game_mode_name.unwrap_or_default().to_string(),
+            ))
+        }
+    };
+
+    let session = connection.session.as_ref();
+    if session.is_none() && !config.allow_anonymous_players {
+        return Err(RequestError::AuthenticationRequired);
+    }
+
+    let wait_id = NEXT_WAIT_ID.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    {
+        let mut state = state.lock().unwrap();
+        let state = &mut *state;
+
+        if state.is_shutting_down {
+            return Err(RequestError::ShuttingDown);
+        }
+
+        if state.is_matchmaking_paused {
+            return Err(RequestError::MatchmakingPaused);
+        }
+
+        if let Some(session) = session {
+            if !state.sessions.is_current(session) {
+                return Err(RequestError::SessionReplaced);
+            }
+        }
+
+        // don't send players to servers that are not running anymore
+        recover_failed_matches(state, config, interface);
+
+        let player_id = session.map(|session| session.player_id.as_str());
+        let is_place_restored = match player_id {
+            Some(player_id) => {
+                let is_place_restored =
+                    state
+                        .open_matches
+                        .get_mut(&game_mode.name)
+                        .is_some_and(|slots| {
+                            reattach_waiting_player(slots, player_id, wait_id, &sender)
+                        });
+                if !is_place_restored {
+                    // the player leaves the queue of the other game mode
+                    if let Some(previous_player) = remove_waiting_player(state, config, |player| {
+                        player.player_id.as_deref() == Some(player_id)
+                    }) {
+                        let _ = previous_player
+                            .match_found_sender
+                            .send(Err(RequestError::SessionReplaced));
+                    }
+                }
+                is_place_restored
+            }
+            None => false,
+        };
+        if is_place_restored {
+            log_info!(
+                "Player '{}' got back its place in the '{}' queue",
+                player_id.unwrap_or_default(),
+                game_mode.name
+            );
+        } else {
+            // the rating calculated from the match results can be trusted more than the one sent by the client
+            let rating = player_id
+                .and_then(|player_id| state.player_ratings.get(player_id))
+                .or(rating);
+            let player = WaitingPlayer {
+                wait_id,
+                player_id: player_id.map(|player_id| player_id.to_string()),
+                rating,
+                queued_at: std::time::Instant::now(),
+                disconnected_at: None,
+                match_found_sender: sender,
+            };
+            add_player_to_queue(state, config, interface, game_mode, player)?;
+        }
+    }
+
+    wait_for_match(&receiver, wait_id, connection, config, state)
+}
+
+/// Waits for the ticket while watching the connection, the player leaves the queue
+/// if the client sends another request or closes the connection before the match is found
+fn wait_for_match(
+    receiver: &std::sync::mpsc::Receiver<Result<MatchTicket, RequestError>>,
+    wait_id: u64,
+    connection: &ClientConnection,
+    config: &Config,
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+) -> Result<Response, RequestError> {
+    let activity = loop {
+        match receiver.recv_timeout(CLIENT_ACTIVITY_CHECK_INTERVAL) {
+            Ok(result) => return result.map(Response::MatchFound),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(RequestError::ServerStopped)
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+        }
+
+        let activity = connection.check_activity();
+        if activity != ClientActivity::Idle {
+            break activity;
+        }
+    };
+
+    let is_disconnected = activity == ClientActivity::Disconnected;
+    let has_left_queue = {
+        let mut state = state.lock().unwrap();
+        let state = &mut *state;
+        let has_left_queue = match &connection.session {
+            // the player can come back from another connection and get its place back
+            Some(_) if is_disconnected => {
+                match find_waiting_player(&mut state.open_matches, wait_id) {
+                    Some(player) => {
+                        player.disconnected_at = Some(std::time::Instant::now());
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => remove_waiting_player(state, config, |player| player.wait_id == wait_id).is_some(),
+        };
+        if has_left_queue && is_disconnected {
+            state.metrics.queue_disconnections_total += 1;
+        } else if has_left_queue {
+            state.metrics.queue_cancellations_total += 1;
+        }
+        has_left_queue
+    };
+
+    if !has_left_queue {
+        // the ticket is already sent, it is too late to cancel
+        return match receiver.recv() {
+            Ok(result) => result.map(Response::MatchFound),
+            Err(_) => Err(RequestError::ServerStopped),
+        };
+    }
+    match &connection.session {
+        Some(session) if is_disconnected => log_info!(
+            "Connection of player '{}' is lost, its place in the queue is kept for {} seconds",
+            session.player_id,
+            config.session_reconnect_timeout_seconds
+        ),
+        _ if is_disconnected => log_info!("Connection is lost while waiting for a match"),
+        _ => log_info!("Waiting for a match is cancelled by the client"),
+    }
+    Err(RequestError::Cancelled)
+}
+
+fn process_cancel_request(
+    connection: &ClientConnection,
+    config: &Config,
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+) -> Result<Response, RequestError> {
+    // the wait on this connection is already cancelled when the request arrives,
+    // a player with a session also leaves the queue that it kept from a lost connection
+    if let Some(session) = &connection.session {
+        let mut state = state.lock().unwrap();
+        if !state.sessions.is_current(session) {
+            return Err(RequestError::SessionReplaced);
+        }
+        if remove_waiting_player(&mut state, config, |player| {
+            player.player_id.as_deref() == Some(session.player_id.as_str())
+        })
+        .is_some()
+        {
+            log_info!("Player '{}' left the queue", session.player_id);
+            state.metrics.queue_cancellations_total += 1;
+        }
+    }
+    Ok(Response::Cancelled)
+}
+
+fn add_player_to_queue(
+    state: &mut MatchmakerState,
+    config: &Config,
+    interface: &str,
+    game_mode: &GameModeConfig,
+    player: WaitingPlayer,
+) -> Result<(), RequestError> {
+    let slots = state
+        .open_matches
+        .entry(game_mode.name.clone())
+        .or_default();
+
+    let candidates: Vec<matching::MatchCandidate> =
+        slots.iter().map(MatchSlot::get_match_candidate).collect();
+    let slot_index = match matching::find_match_for_player(
+        &candidates,
+        player.rating,
+        game_mode.players_per_match,
+        &get_rating_window_settings(config),
+    ) {
+        Some(slot_index) => slot_index,
+        None => {
+            slots.push(MatchSlot {
+                server_id: None,
+                created_at: std::time::Instant::now(),
+                players_waiting: Vec::new(),
+                respawns_count: 0,
+                fill_with_bots: false,
+            });
+            slots.len() - 1
+        }
+    };
+
+    let slot = &mut slots[slot_index];
+    slot.players_waiting.push(player);
+    if slot.server_id.is_none() && slot.is_complete(game_mode.players_per_match) {
+        match get_server_for_new_match(
+            config,
+            game_mode,
+            interface,
+            &mut state.warm_pool,
+            &mut state.server_registry,
+            &mut state.port_reservations,
+            &mut state.metrics,
+        ) {
+            Ok(server_id) => slot.server_id = Some(server_id),
+            Err(error) => {
+                // the player whose request needed the server gets the error,
+                // the others keep waiting for a server
+                slot.players_waiting.pop();
+                if slot.players_waiting.is_empty() {
+                    slots.remove(slot_index);
+                }
+                return Err(error);
+            }
+        }
+    }
+    hand_off_ready_matches(state, config);
+    Ok(())
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    tls_server_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+    config: &Config,
+    interface: &str,
+    state: std::sync::Arc<std::sync::Mutex<MatchmakerState>>,
+    shutdown_requested: &AtomicBool,
+) {
+    let mut tls_connection = match tls_server_config {
+        Some(tls_server_config) => match rustls::ServerConnection::new(tls_server_config) {
+            Ok(tls_connection) => Some(tls_connection),
+            Err(error) => {
+                log_error!("Problem setting up TLS for the connection: {:?}", error);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let authenticator = state.lock().unwrap().authenticator.clone();
+    let mut connection = ClientConnection {
+        socket: &stream,
+        // every connection starts with the legacy protocol until the client asks for another version
+        protocol_version: protocol::LEGACY_PROTOCOL_VERSION,
+        session: None,
+        authenticator,
+        has_buffered_input: false,
+    };
+
+    let has_session = std::cell::Cell::new(false);
+    let get_idle_timeout = || {
+        if has_session.get() {
+            SESSION_IDLE_TIMEOUT
+        } else {
+            ANONYMOUS_CLIENT_IDLE_TIMEOUT
+        }
+    };
+
+    let process_line = |request_line: &str, has_buffered_input: bool| {
+        state.lock().unwrap().metrics.requests_total += 1;
+        connection.has_buffered_input = has_buffered_input;
+
+        // the response is sent in the same protocol version as the request
+        if connection.protocol_version == protocol::JSON_PROTOCOL_VERSION {
+            let (id, result) = match protocol::parse_json_request(request_line) {
+                Ok(message) => (
+                    message.id,
+                    process_request(
+                        message.request,
+                        &mut connection,
+                        config,
+                        interface,
+                        state.clone(),
+                    ),
+                ),
+                Err(error) => (None, Err(error)),
+            };
+            has_session.set(connection.session.is_some());
+            Some(protocol::format_json_response(&protocol::ResponseMessage {
+                id,
+                response: get_response_from_result(result),
+            }))
+        } else {
+            let http_request: Vec<String> = request_line
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect();
+
+            let result = match protocol::parse_legacy_request(&http_request) {
+                Ok(request) => {
+                    process_request(request, &mut connection, config, interface, state.clone())
+                }
+                Err(error) => Err(error),
+            };
+            has_session.set(connection.session.is_some());
+            if let Err(RequestError::UnknownRequest) = result {
+                log_warning!("Unknown request: {:?}", http_request);
+            }
+            // legacy responses have no line terminator, so only the request that interrupted
+            // the wait is answered, otherwise the client would get both replies glued together
+            if let Err(RequestError::Cancelled) = result {
+                return None;
+            }
+            Some(protocol::format_legacy_response(&get_response_from_result(
+                result,
+            )))
+        }
+    };
+
+    match &mut tls_connection {
+        Some(tls_connection) => {
+            // the handshake is done with the first read
+            let mut socket = &stream;
+            let tls_stream = rustls::Stream::new(tls_connection, &mut socket);
+            serve_line_protocol_over(
+                &stream,
+                tls_stream,
+                get_idle_timeout,
+                Some(shutdown_requested),
+                process_line,
+            );
+            tls_connection.send_close_notify();
+            let _ = tls_connection.complete_io(&mut socket);
+        }
+        None => serve_line_protocol_over(
+            &stream,
+            &stream,
+            get_idle_timeout,
+            Some(shutdown_requested),
+            process_line,
+        ),
+    }
+
+    // the player keeps its place in the queue and can get it back from another connection
+    if let Some(session) = connection.session {
+        state.lock().unwrap().sessions.mark_disconnected(&session);
+    }
+}
+
+/// Tells the client that connected while all the connection threads are busy to try again later
+fn reject_connection(
+    stream: TcpStream,
+    tls_server_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+) {
+    let deadline = std::time::Instant::now() + REJECTED_CLIENT_TIMEOUT;
+    let _ = stream.set_read_timeout(Some(REJECTED_CLIENT_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(REJECTED_CLIENT_TIMEOUT));
+    // every connection starts with the legacy protocol
+    let response = protocol::format_legacy_response(&Response::from(RequestError::TooManyClients));
+
+    let mut socket = &stream;
+    match tls_server_config.map(rustls::ServerConnection::new) {
+        Some(Ok(mut tls_connection)) => {
+            let mut tls_stream = rustls::Stream::new(&mut tls_connection, &mut socket);
+            if tls_stream.write_all(response.as_bytes()).is_ok() {
+                tls_connection.send_close_notify();
+                let _ = tls_connection.complete_io(&mut socket);
+            }
+        }
+        Some(Err(error)) => log_error!("Problem setting up TLS for the connection: {:?}", error),
+        None => {
+            let _ = socket.write_all(response.as_bytes());
+        }
+    }
+
+    // closing the socket with unread requests in it resets the connection,
+    // and the client may lose the response
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+    let mut buffer = [0u8; 4096];
+    let mut input_size = 0;
+    while input_size < REJECTED_CLIENT_MAX_INPUT_SIZE {
+        let time_left = deadline.saturating_duration_since(std::time::Instant::now());
+        if time_left.is_zero() || stream.set_read_timeout(Some(time_left)).is_err() {
+            break;
+        }
+        match socket.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(size) => input_size += size,
+        }
+    }
+}
+
+fn serve_line_protocol<F>(
+    stream: &TcpStream,
+    idle_timeout: std::time::Duration,
+    mut process_line: F,
+) where
+    F: FnMut(&str) -> String,
+{
+    serve_line_protocol_over(
+        stream,
+        stream,
+        || idle_timeout,
+        None,
+        |request_line, _| Some(process_line(request_line)),
+    );
+}
+
+/// Serves the lines that are read from and written to a stream on top of the socket, like TLS.
+/// Along with the line the processing gets whether the next request is already read from the socket,
+/// and returns the response if there is one. The connection is closed once the client sends nothing
+/// for the idle timeout, which is taken again after every request, or once the shutdown is requested
+/// while the connection waits for the next request.
+fn serve_line_protocol_over<S, T, F>(
+    socket: &TcpStream,
+    stream: S,
+    get_idle_timeout: T,
+    shutdown_requested: Option<&AtomicBool>,
+    mut process_line: F,
+) where
+    S: Read + Write,
+    T: Fn() -> std::time::Duration,
+    F: FnMut(&str, bool) -> Option<String>,
+{
+    let mut reader = BufReader::new(stream);
+
+    logging::set_context(LogContext {
+        peer_address: socket.peer_addr().ok().map(|address| address.to_string()),
+        request_id: None,
+    });
+    log_debug!("Connection accepted");
+
+    let mut request_line = String::new();
+    let mut idle_deadline = std::time::Instant::now() + get_idle_timeout();
+    loop {
+        if shutdown_requested
+            .is_some_and(|shutdown_requested| shutdown_requested.load(Ordering::Relaxed))
+        {
+            log_debug!("Closing connection, the matchmaker is shutting down");
+            break;
+        }
+        let time_left = idle_deadline.saturating_duration_since(std::time::Instant::now());
+        if time_left.is_zero() {
+            log_debug!("Closing idle connection");
+            break;
+        }
+        let read_timeout = match shutdown_requested {
+            Some(_) => time_left.min(CLIENT_SHUTDOWN_CHECK_INTERVAL),
+            None => time_left,
+        };
+        if socket.set_read_timeout(Some(read_timeout)).is_err() {
+            break;
+        }
+        match reader.read_line(&mut request_line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            // the part of the line that is already read stays in request_line
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(_) => break,
+        }
+
+        logging::set_request_id(Some(NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)));
+        log_debug!("Received request: {}", request_line.trim_end());
+        let response = process_line(&request_line, !reader.buffer().is_empty());
+
+        if let Some(response) = response {
+            log_debug!("Responding with: {}", response.trim_end());
+            if let Err(error) = reader.get_mut().write_all(response.as_bytes()) {
+                log_warning!("Problem sending the response: {:?}", error);
+                break;
+            }
+        }
+        request_line.clear();
+        idle_deadline = std::time::Instant::now() + get_idle_timeout();
+    }
+
+    log_debug!("Connection closed");
+    // the thread can be reused for another connection
+    logging::set_context(LogContext::default());
+}
+
+fn get_response_from_result(result: Result<Response, RequestError>) -> Response {
+    match result {
+        Ok(response) => response,
+        Err(error) => {
+            log_info!("Request failed: {}", error);
+            Response::from(error)
+        }
+    }
+}
+
+fn get_available_port(
+    interface: &str,
+    port_reservations: &mut PortReservations,
+    metrics: &mut Metrics,
+) -> Option<u16> {
+    // the reservations know about servers that haven't opened their ports yet,
+    // probing catches the ports that are used by other processes
+    let port = port_reservations.reserve_port(|port| is_port_available(interface, port));
+    if port.is_none() {
+        metrics.no_ports_available_total += 1;
+    }
+    port
 }
 
 fn is_port_available(interface: &str, port: u16) -> bool {
@@ -318,3 +2205,328 @@ fn is_port_available(interface: &str, port: u16) -> bool {
         Err(_) => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_state() -> MatchmakerState {
+        MatchmakerState {
+            open_matches: HashMap::new(),
+            server_registry: ServerRegistry::new(),
+            port_reservations: PortReservations::new(8000..=8999),
+            is_shutting_down: false,
+            is_matchmaking_paused: false,
+            metrics: Metrics::default(),
+            player_ratings: PlayerRatings::new(),
+            warm_pool: WarmPool::new(),
+            sessions: SessionRegistry::new(),
+            authenticator: std::sync::Arc::new(authentication::NoAuthenticator),
+            tls_certificates: None,
+        }
+    }
+
+    fn create_test_config(fill_with_bots: bool, warm_servers_count: usize) -> Config {
+        let mut config = config::get_default_config();
+        config.max_queue_wait_seconds = Some(0);
+        config.game_modes[0].fill_with_bots = fill_with_bots;
+        config.game_modes[0].warm_servers_count = warm_servers_count;
+        config
+    }
+
+    /// Registers a ready server which process exits right away, nothing is listening on its port
+    fn register_test_server(state: &mut MatchmakerState, working_directory: &Path) -> ServerId {
+        let process = std::process::Command::new("true")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let server_id = state.server_registry.register(
+            process,
+            8000,
+            "join_token".to_string(),
+            "server_token".to_string(),
+            working_directory.to_string_lossy().to_string(),
+            None,
+        );
+        state.server_registry.mark_ready(server_id);
+        server_id
+    }
+
+    fn add_test_player(
+        state: &mut MatchmakerState,
+        server_id: Option<ServerId>,
+        wait_id: u64,
+        player_id: Option<&str>,
+    ) -> std::sync::mpsc::Receiver<Result<MatchTicket, RequestError>> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        state
+            .open_matches
+            .entry("default".to_string())
+            .or_default()
+            .push(MatchSlot {
+                server_id,
+                created_at: std::time::Instant::now(),
+                players_waiting: vec![WaitingPlayer {
+                    wait_id,
+                    player_id: player_id.map(str::to_string),
+                    rating: None,
+                    queued_at: std::time::Instant::now(),
+                    disconnected_at: None,
+                    match_found_sender: sender,
+                }],
+                respawns_count: 0,
+                fill_with_bots: false,
+            });
+        receiver
+    }
+
+    /// Waits for the processes of the test servers to exit, so they don't stay as zombies
+    fn reap_test_servers(state: &mut MatchmakerState) {
+        while state.server_registry.running_servers_count() > 0 {
+            state.server_registry.reap_exited_servers();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    fn get_queued_players_count(state: &MatchmakerState) -> usize {
+        state
+            .open_matches
+            .values()
+            .flatten()
+            .map(|slot| slot.players_waiting.len())
+            .sum()
+    }
+
+    /// Returns the matchmaker side of a connection which client side is also returned
+    fn create_test_connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        (socket, client)
+    }
+
+    #[test]
+    fn test_players_that_waited_too_long_leave_the_queue() {
+        let config = create_test_config(false, 0);
+        let mut state = create_test_state();
+        let receiver = add_test_player(&mut state, None, 1, None);
+
+        expire_waiting_players(&mut state, &config);
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Err(RequestError::QueueTimeout))
+        ));
+        assert_eq!(get_queued_players_count(&state), 0);
+        assert_eq!(state.metrics.queue_timeouts_total, 1);
+    }
+
+    #[test]
+    fn test_match_that_waited_too_long_is_filled_with_bots() {
+        let config = create_test_config(true, 1);
+        let working_directory =
+            std::env::temp_dir().join(format!("matchmaker_bots_test_{}", std::process::id()));
+        fs::create_dir_all(&working_directory).unwrap();
+        let mut state = create_test_state();
+        let server_id = register_test_server(&mut state, &working_directory);
+        state.warm_pool.add_server("default", server_id);
+        let receiver = add_test_player(&mut state, None, 1, Some("p1"));
+
+        expire_waiting_players(&mut state, &config);
+        // the players of the game modes with bots stay in the queue
+        assert!(receiver.try_recv().is_err());
+        assert!(state.open_matches["default"][0].fill_with_bots);
+
+        start_servers_for_complete_matches(&mut state, &config, "127.0.0.1");
+        hand_off_ready_matches(&mut state, &config);
+
+        let ticket = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(ticket.token, "join_token");
+        assert!(ticket.fill_with_bots);
+        assert_eq!(get_queued_players_count(&state), 0);
+        assert_eq!(state.metrics.warm_servers_used_total, 1);
+        assert_eq!(state.metrics.bot_filled_matches_total, 1);
+        assert!(state
+            .server_registry
+            .get(server_id)
+            .unwrap()
+            .matched_players
+            .contains("p1"));
+        reap_test_servers(&mut state);
+        let _ = fs::remove_dir_all(&working_directory);
+    }
+
+    #[test]
+    fn test_server_of_abandoned_match_goes_to_warm_pool() {
+        let config = create_test_config(false, 1);
+        let mut state = create_test_state();
+        let server_id = register_test_server(&mut state, Path::new("dir"));
+        add_test_player(&mut state, Some(server_id), 1, None);
+
+        assert!(remove_waiting_player(&mut state, &config, |player| player.wait_id == 1).is_some());
+
+        assert!(state.open_matches["default"].is_empty());
+        assert_eq!(state.warm_pool.get_idle_servers_count("default"), 1);
+        assert!(state.server_registry.is_running(server_id));
+        reap_test_servers(&mut state);
+    }
+
+    #[test]
+    fn test_server_of_abandoned_match_is_stopped_if_warm_pool_is_full() {
+        let config = create_test_config(false, 0);
+        let mut state = create_test_state();
+        let server_id = register_test_server(&mut state, Path::new("dir"));
+        add_test_player(&mut state, Some(server_id), 1, None);
+
+        assert!(remove_waiting_player(&mut state, &config, |player| player.wait_id == 1).is_some());
+
+        assert_eq!(state.warm_pool.get_idle_servers_count("default"), 0);
+        assert!(!state.server_registry.is_running(server_id));
+        reap_test_servers(&mut state);
+    }
+
+    #[test]
+    fn test_server_of_merged_match_goes_to_warm_pool() {
+        let config = create_test_config(false, 1);
+        let mut state = create_test_state();
+        let first_server_id = register_test_server(&mut state, Path::new("dir"));
+        let second_server_id = register_test_server(&mut state, Path::new("dir"));
+        add_test_player(&mut state, Some(first_server_id), 1, None);
+        add_test_player(&mut state, Some(second_server_id), 2, None);
+
+        merge_waiting_matches(&mut state, &config);
+
+        assert_eq!(state.open_matches["default"].len(), 1);
+        assert_eq!(state.open_matches["default"][0].players_waiting.len(), 2);
+        assert_eq!(state.warm_pool.get_idle_servers_count("default"), 1);
+        assert!(state.server_registry.is_running(first_server_id));
+        assert!(state.server_registry.is_running(second_server_id));
+        reap_test_servers(&mut state);
+    }
+
+    #[test]
+    fn test_disconnected_client_leaves_the_queue() {
+        let config = create_test_config(false, 0);
+        let mut state = create_test_state();
+        let receiver = add_test_player(&mut state, None, 1, None);
+        let state = std::sync::Arc::new(std::sync::Mutex::new(state));
+        let (socket, client) = create_test_connection();
+        drop(client);
+        let connection = ClientConnection {
+            socket: &socket,
+            protocol_version: 2,
+            session: None,
+            authenticator: std::sync::Arc::new(authentication::NoAuthenticator),
+            has_buffered_input: false,
+        };
+
+        let result = wait_for_match(&receiver, 1, &connection, &config, state.clone());
+
+        assert!(matches!(result, Err(RequestError::Cancelled)));
+        let state = state.lock().unwrap();
+        assert_eq!(get_queued_players_count(&state), 0);
+        assert_eq!(state.metrics.queue_disconnections_total, 1);
+    }
+
+    #[test]
+    fn test_disconnected_player_with_session_keeps_place_in_queue() {
+        let config = create_test_config(false, 0);
+        let mut state = create_test_state();
+        let session = state.sessions.open_session("p1", "", false, false).unwrap();
+        let receiver = add_test_player(&mut state, None, 1, Some("p1"));
+        let state = std::sync::Arc::new(std::sync::Mutex::new(state));
+        let (socket, client) = create_test_connection();
+        drop(client);
+        let connection = ClientConnection {
+            socket: &socket,
+            protocol_version: 2,
+            session: Some(session),
+            authenticator: std::sync::Arc::new(authentication::NoAuthenticator),
+            has_buffered_input: false,
+        };
+
+        let result = wait_for_match(&receiver, 1, &connection, &config, state.clone());
+
+        assert!(matches!(result, Err(RequestError::Cancelled)));
+        let state = state.lock().unwrap();
+        let player = &state.open_matches["default"][0].players_waiting[0];
+        assert!(player.disconnected_at.is_some());
+        assert_eq!(state.metrics.queue_disconnections_total, 1);
+    }
+
+    #[test]
+    fn test_cancel_request_removes_player_with_session_from_queue() {
+        let config = create_test_config(false, 0);
+        let mut state = create_test_state();
+        let session = state.sessions.open_session("p1", "", false, false).unwrap();
+        let _receiver = add_test_player(&mut state, None, 1, Some("p1"));
+        let state = std::sync::Arc::new(std::sync::Mutex::new(state));
+        let (socket, _client) = create_test_connection();
+        let connection = ClientConnection {
+            socket: &socket,
+            protocol_version: 2,
+            session: Some(session),
+            authenticator: std::sync::Arc::new(authentication::NoAuthenticator),
+            has_buffered_input: false,
+        };
+
+        let result = process_cancel_request(&connection, &config, state.clone());
+
+        assert!(matches!(result, Ok(Response::Cancelled)));
+        let state = state.lock().unwrap();
+        assert_eq!(get_queued_players_count(&state), 0);
+        assert_eq!(state.metrics.queue_cancellations_total, 1);
+    }
+
+    #[test]
+    fn test_shutdown_releases_waiting_players_and_servers() {
+        let mut state = create_test_state();
+        let match_server_id = register_test_server(&mut state, Path::new("dir"));
+        let warm_server_id = register_test_server(&mut state, Path::new("dir"));
+        state.warm_pool.add_server("default", warm_server_id);
+        let receiver = add_test_player(&mut state, Some(match_server_id), 1, None);
+        let state = std::sync::Mutex::new(state);
+
+        begin_shutdown(&state);
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Err(RequestError::ShuttingDown))
+        ));
+        let mut state = state.into_inner().unwrap();
+        assert!(state.is_shutting_down);
+        assert!(state.open_matches.is_empty());
+        assert!(!state.server_registry.is_running(match_server_id));
+        assert!(!state.server_registry.is_running(warm_server_id));
+        assert_eq!(state.warm_pool.get_total_idle_servers_count(), 0);
+        reap_test_servers(&mut state);
+    }
+
+    #[test]
+    fn test_idle_connection_is_closed_on_shutdown() {
+        let (socket, _client) = create_test_connection();
+        let shutdown_requested = std::sync::Arc::new(AtomicBool::new(false));
+        let connection_thread = {
+            let shutdown_requested = shutdown_requested.clone();
+            std::thread::spawn(move || {
+                serve_line_protocol_over(
+                    &socket,
+                    &socket,
+                    || SESSION_IDLE_TIMEOUT,
+                    Some(&shutdown_requested),
+                    |_, _| None,
+                );
+            })
+        };
+
+        std::thread::sleep(CLIENT_SHUTDOWN_CHECK_INTERVAL);
+        assert!(!connection_thread.is_finished());
+        shutdown_requested.store(true, Ordering::Relaxed);
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !connection_thread.is_finished() {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
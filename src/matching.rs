@@ -0,0 +1,235 @@
+use std::time::Duration;
+
+/// Describes how far apart the ratings of players can be to be put into the same match
+pub struct RatingWindowSettings {
+    pub initial_rating_window: f32,
+    pub rating_window_growth_per_second: f32,
+}
+
+/// What the matching needs to know about a match that is waiting for players
+pub struct MatchCandidate {
+    // average rating of the rated players in the match, None if nobody there has a rating
+    pub average_rating: Option<f32>,
+    pub players_count: usize,
+    pub waiting_time: Duration,
+}
+
+pub fn get_rating_window(settings: &RatingWindowSettings, waiting_time: Duration) -> f32 {
+    settings.initial_rating_window
+        + settings.rating_window_growth_per_second * waiting_time.as_secs_f32()
+}
+
+pub fn get_average_rating<I>(ratings: I) -> Option<f32>
+where
+    I: Iterator<Item = Option<f32>>,
+{
+    let mut sum = 0.0;
+    let mut count = 0;
+    for rating in ratings.flatten() {
+        sum += rating;
+        count += 1;
+    }
+
+    if count > 0 {
+        Some(sum / count as f32)
+    } else {
+        None
+    }
+}
+
+// players without rating can be matched with anyone
+fn get_rating_distance(first: Option<f32>, second: Option<f32>) -> f32 {
+    match (first, second) {
+        (Some(first), Some(second)) => (first - second).abs(),
+        _ => 0.0,
+    }
+}
+
+/// Finds the match with free places that is the closest by rating to the player and that is
+/// waiting long enough for its rating window to include the player's rating.
+/// From the equally good matches the one that waits the longest is chosen.
+pub fn find_match_for_player(
+    candidates: &[MatchCandidate],
+    rating: Option<f32>,
+    players_per_match: usize,
+    settings: &RatingWindowSettings,
+) -> Option<usize> {
+    let mut best_match: Option<(usize, f32)> = None;
+    for (index, candidate) in candidates.iter().enumerate() {
+        if candidate.players_count >= players_per_match {
+            continue;
+        }
+
+        let distance = get_rating_distance(candidate.average_rating, rating);
+        if distance > get_rating_window(settings, candidate.waiting_time) {
+            continue;
+        }
+
+        let is_better = match best_match {
+            Some((best_index, best_distance)) => {
+                distance < best_distance
+                    || (distance == best_distance
+                        && candidate.waiting_time > candidates[best_index].waiting_time)
+            }
+            None => true,
+        };
+        if is_better {
+            best_match = Some((index, distance));
+        }
+    }
+
+    best_match.map(|(index, _)| index)
+}
+
+/// Finds two matches whose rating windows have grown enough to combine their players into one
+/// match. Returns the indices of the match to keep and the match to move the players from.
+pub fn find_matches_to_merge(
+    candidates: &[MatchCandidate],
+    players_per_match: usize,
+    settings: &RatingWindowSettings,
+) -> Option<(usize, usize)> {
+    for (first_index, first) in candidates.iter().enumerate() {
+        for (second_index, second) in candidates.iter().enumerate().skip(first_index + 1) {
+            if first.players_count + second.players_count > players_per_match {
+                continue;
+            }
+
+            // the player that waits longer is ready to accept a wider range of ratings
+            let rating_window = get_rating_window(
+                settings,
+                std::cmp::max(first.waiting_time, second.waiting_time),
+            );
+            if get_rating_distance(first.average_rating, second.average_rating) > rating_window {
+                continue;
+            }
+
+            return if first.waiting_time >= second.waiting_time {
+                Some((first_index, second_index))
+            } else {
+                Some((second_index, first_index))
+            };
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> RatingWindowSettings {
+        RatingWindowSettings {
+            initial_rating_window: 100.0,
+            rating_window_growth_per_second: 10.0,
+        }
+    }
+
+    fn candidate(
+        average_rating: Option<f32>,
+        players_count: usize,
+        waiting_secs: u64,
+    ) -> MatchCandidate {
+        MatchCandidate {
+            average_rating,
+            players_count,
+            waiting_time: Duration::from_secs(waiting_secs),
+        }
+    }
+
+    #[test]
+    fn test_rating_window_grows_with_waiting_time() {
+        assert_eq!(
+            get_rating_window(&settings(), Duration::from_secs(0)),
+            100.0
+        );
+        assert_eq!(
+            get_rating_window(&settings(), Duration::from_secs(30)),
+            400.0
+        );
+    }
+
+    #[test]
+    fn test_average_rating_ignores_unrated_players() {
+        let ratings = vec![Some(1000.0), None, Some(1200.0)];
+        assert_eq!(get_average_rating(ratings.into_iter()), Some(1100.0));
+        assert_eq!(get_average_rating(vec![None, None].into_iter()), None);
+    }
+
+    #[test]
+    fn test_player_is_matched_with_the_closest_rating() {
+        let candidates = vec![
+            candidate(Some(1000.0), 1, 5),
+            candidate(Some(1450.0), 1, 5),
+            candidate(Some(1550.0), 1, 5),
+        ];
+        assert_eq!(
+            find_match_for_player(&candidates, Some(1520.0), 2, &settings()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_player_is_not_matched_outside_of_the_rating_window() {
+        let candidates = vec![candidate(Some(1000.0), 1, 0)];
+        assert_eq!(
+            find_match_for_player(&candidates, Some(1300.0), 2, &settings()),
+            None
+        );
+
+        let candidates = vec![candidate(Some(1000.0), 1, 20)];
+        assert_eq!(
+            find_match_for_player(&candidates, Some(1300.0), 2, &settings()),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_full_matches_are_skipped() {
+        let candidates = vec![candidate(Some(1000.0), 2, 5), candidate(None, 1, 0)];
+        assert_eq!(
+            find_match_for_player(&candidates, Some(1000.0), 2, &settings()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_unrated_player_joins_the_longest_waiting_match() {
+        let candidates = vec![
+            candidate(Some(1000.0), 1, 5),
+            candidate(Some(2000.0), 1, 10),
+        ];
+        assert_eq!(
+            find_match_for_player(&candidates, None, 2, &settings()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_matches_are_merged_once_rating_window_is_wide_enough() {
+        let candidates = vec![
+            candidate(Some(1000.0), 1, 5),
+            candidate(Some(1300.0), 1, 10),
+        ];
+        assert_eq!(find_matches_to_merge(&candidates, 2, &settings()), None);
+
+        let candidates = vec![
+            candidate(Some(1000.0), 1, 5),
+            candidate(Some(1300.0), 1, 20),
+        ];
+        assert_eq!(
+            find_matches_to_merge(&candidates, 2, &settings()),
+            Some((1, 0))
+        );
+    }
+
+    #[test]
+    fn test_matches_are_not_merged_above_players_per_match() {
+        let candidates = vec![candidate(None, 3, 5), candidate(None, 2, 10)];
+        assert_eq!(find_matches_to_merge(&candidates, 4, &settings()), None);
+        assert_eq!(
+            find_matches_to_merge(&candidates, 5, &settings()),
+            Some((1, 0))
+        );
+    }
+}
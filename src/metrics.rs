@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use crate::logging::log_error;
+use crate::MatchmakerState;
+
+// requests are served one at a time, so a slow or oversized request can't hold the endpoint for long
+const METRICS_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const METRICS_MAX_LINE_LENGTH: usize = 8 * 1024;
+const METRICS_MAX_HEADER_LINES: usize = 100;
+
+/// Counters of the events that happened since the matchmaker started
+#[derive(Default)]
+pub struct Metrics {
+    pub requests_total: u64,
+    // connections that were closed right away because all the connection threads were busy
+    pub rejected_connections_total: u64,
+    pub matches_created_total: u64,
+    // matches that the dedicated servers reported as finished
+    pub matches_finished_total: u64,
+    pub server_spawn_failures_total: u64,
+    pub no_ports_available_total: u64,
+    // servers that were not started because the capacity limits were reached
+    pub server_capacity_rejections_total: u64,
+    pub authentication_failures_total: u64,
+    pub queue_cancellations_total: u64,
+    pub queue_disconnections_total: u64,
+    pub queue_timeouts_total: u64,
+    pub bot_filled_matches_total: u64,
+    pub server_crashes_total: u64,
+    pub server_startup_timeouts_total: u64,
+    // servers that exited before their match was handed off to them
+    pub server_early_exits_total: u64,
+    pub server_respawns_total: u64,
+    // new matches that got a server from the warm pool instead of starting one
+    pub warm_servers_used_total: u64,
+    // time from joining the queue to getting a match, summed over all the players that got one
+    pub match_wait_seconds_sum: f64,
+    pub match_wait_seconds_count: u64,
+}
+
+/// Values that are measured at the moment the metrics are requested
+pub struct MetricsSnapshot {
+    // by game mode name
+    pub queued_players: BTreeMap<String, usize>,
+    pub running_servers: usize,
+    pub idle_warm_servers: usize,
+    pub connected_sessions: usize,
+}
+
+impl Metrics {
+    pub fn add_match_wait_time(&mut self, wait_time: std::time::Duration) {
+        self.match_wait_seconds_sum += wait_time.as_secs_f64();
+        self.match_wait_seconds_count += 1;
+    }
+}
+
+/// Formats the metrics in the Prometheus text exposition format
+pub fn format_metrics(metrics: &Metrics, snapshot: &MetricsSnapshot) -> String {
+    let mut text = String::new();
+    let mut add_metric =
+        |name: &str, metric_type: &str, help: &str, values: &[(String, String)]| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, metric_type);
+            for (labels_and_suffix, value) in values {
+                let _ = writeln!(text, "{}{} {}", name, labels_and_suffix, value);
+            }
+        };
+
+    add_metric(
+        "matchmaker_requests_total",
+        "counter",
+        "Number of requests received from the clients.",
+        &[(String::new(), metrics.requests_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_rejected_connections_total",
+        "counter",
+        "Number of client connections that were rejected because too many clients were served.",
+        &[(
+            String::new(),
+            metrics.rejected_connections_total.to_string(),
+        )],
+    );
+    add_metric(
+        "matchmaker_authentication_failures_total",
+        "counter",
+        "Number of hello requests with auth that didn't prove the identity of the player.",
+        &[(
+            String::new(),
+            metrics.authentication_failures_total.to_string(),
+        )],
+    );
+    add_metric(
+        "matchmaker_queue_cancellations_total",
+        "counter",
+        "Number of players that cancelled waiting for a match.",
+        &[(String::new(), metrics.queue_cancellations_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_queue_disconnections_total",
+        "counter",
+        "Number of players that closed the connection while waiting for a match.",
+        &[(
+            String::new(),
+            metrics.queue_disconnections_total.to_string(),
+        )],
+    );
+    add_metric(
+        "matchmaker_queue_timeouts_total",
+        "counter",
+        "Number of players that got no match within the maximum queue wait.",
+        &[(String::new(), metrics.queue_timeouts_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_matches_created_total",
+        "counter",
+        "Number of matches that got all their players.",
+        &[(String::new(), metrics.matches_created_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_bot_filled_matches_total",
+        "counter",
+        "Number of matches that started with bots in place of the missing players.",
+        &[(String::new(), metrics.bot_filled_matches_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_matches_finished_total",
+        "counter",
+        "Number of matches that the dedicated servers reported as finished.",
+        &[(String::new(), metrics.matches_finished_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_server_spawn_failures_total",
+        "counter",
+        "Number of dedicated servers that could not be started.",
+        &[(
+            String::new(),
+            metrics.server_spawn_failures_total.to_string(),
+        )],
+    );
+    add_metric(
+        "matchmaker_no_ports_available_total",
+        "counter",
+        "Number of times a dedicated server could not be started because all the ports were in use.",
+        &[(String::new(), metrics.no_ports_available_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_server_capacity_rejections_total",
+        "counter",
+        "Number of times a dedicated server was not started because the capacity limits were reached.",
+        &[(
+            String::new(),
+            metrics.server_capacity_rejections_total.to_string(),
+        )],
+    );
+    add_metric(
+        "matchmaker_server_crashes_total",
+        "counter",
+        "Number of dedicated servers that exited with an error or were terminated by a signal.",
+        &[(String::new(), metrics.server_crashes_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_server_startup_timeouts_total",
+        "counter",
+        "Number of dedicated servers that were killed because they didn't open their port in time.",
+        &[(
+            String::new(),
+            metrics.server_startup_timeouts_total.to_string(),
+        )],
+    );
+    add_metric(
+        "matchmaker_server_early_exits_total",
+        "counter",
+        "Number of dedicated servers that exited before the match was handed off.",
+        &[(String::new(), metrics.server_early_exits_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_server_respawns_total",
+        "counter",
+        "Number of dedicated servers started to replace the ones that exited before the match was handed off.",
+        &[(String::new(), metrics.server_respawns_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_warm_servers_used_total",
+        "counter",
+        "Number of new matches that got a dedicated server from the warm pool.",
+        &[(String::new(), metrics.warm_servers_used_total.to_string())],
+    );
+    add_metric(
+        "matchmaker_match_wait_seconds",
+        "summary",
+        "Time players waited in the queue before getting a match.",
+        &[
+            (
+                "_sum".to_string(),
+                metrics.match_wait_seconds_sum.to_string(),
+            ),
+            (
+                "_count".to_string(),
+                metrics.match_wait_seconds_count.to_string(),
+            ),
+        ],
+    );
+    let queued_players: Vec<(String, String)> = snapshot
+        .queued_players
+        .iter()
+        .map(|(game_mode_name, count)| {
+            (
+                format!("{{game_mode=\"{}\"}}", escape_label_value(game_mode_name)),
+                count.to_string(),
+            )
+        })
+        .collect();
+    add_metric(
+        "matchmaker_queued_players",
+        "gauge",
+        "Number of players waiting for a match.",
+        &queued_players,
+    );
+    add_metric(
+        "matchmaker_running_servers",
+        "gauge",
+        "Number of dedicated servers that haven't exited yet.",
+        &[(String::new(), snapshot.running_servers.to_string())],
+    );
+    add_metric(
+        "matchmaker_idle_warm_servers",
+        "gauge",
+        "Number of dedicated servers in the warm pool that are waiting for a match.",
+        &[(String::new(), snapshot.idle_warm_servers.to_string())],
+    );
+    add_metric(
+        "matchmaker_connected_sessions",
+        "gauge",
+        "Number of players that identified themselves and are still connected.",
+        &[(String::new(), snapshot.connected_sessions.to_string())],
+    );
+
+    text
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn get_metrics_snapshot(state: &MatchmakerState) -> MetricsSnapshot {
+    MetricsSnapshot {
+        queued_players: state
+            .open_matches
+            .iter()
+            .map(|(game_mode_name, slots)| {
+                (
+                    game_mode_name.clone(),
+                    slots.iter().map(|slot| slot.players_waiting.len()).sum(),
+                )
+            })
+            .collect(),
+        running_servers: state.server_registry.running_servers_count(),
+        idle_warm_servers: state.warm_pool.get_total_idle_servers_count(),
+        connected_sessions: state.sessions.connected_sessions_count(),
+    }
+}
+
+/// Serves the metrics over HTTP on a separate thread, requests are handled one at a time
+pub fn start_metrics_thread(listener: TcpListener, state: Arc<Mutex<MatchmakerState>>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(error) = handle_metrics_connection(&stream, &state) {
+                        log_error!("Problem responding to a metrics request: {:?}", error);
+                    }
+                }
+                Err(error) => {
+                    log_error!("Problem accepting a metrics connection: {:?}", error);
+                }
+            }
+        }
+    });
+}
+
+fn handle_metrics_connection(
+    stream: &TcpStream,
+    state: &Mutex<MatchmakerState>,
+) -> std::io::Result<()> {
+    let deadline = std::time::Instant::now() + METRICS_REQUEST_TIMEOUT;
+    stream.set_write_timeout(Some(METRICS_REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let request_line = read_request_line(&mut reader, deadline)?;
+    // the headers are not used, but they need to be read before responding
+    let mut header_lines_count = 0;
+    loop {
+        let header_line = read_request_line(&mut reader, deadline)?;
+        if header_line.trim_end().is_empty() {
+            break;
+        }
+        header_lines_count += 1;
+        if header_lines_count > METRICS_MAX_HEADER_LINES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "too many header lines",
+            ));
+        }
+    }
+
+    let mut request_parts = request_line.split_whitespace();
+    let (status, body) = match (request_parts.next(), request_parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let state = state.lock().unwrap();
+            let snapshot = get_metrics_snapshot(&state);
+            ("200 OK", format_metrics(&state.metrics, &snapshot))
+        }
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+
+    let mut writer = stream;
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Reads one line of the request, an empty string means the connection is closed
+fn read_request_line(
+    reader: &mut BufReader<&TcpStream>,
+    deadline: std::time::Instant,
+) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        let time_left = deadline.saturating_duration_since(std::time::Instant::now());
+        if time_left.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "request is not received in time",
+            ));
+        }
+        reader.get_ref().set_read_timeout(Some(time_left))?;
+
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            // the deadline is checked on the next iteration
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(error) => return Err(error),
+        };
+        if available.is_empty() {
+            break;
+        }
+        let (consumed_size, is_line_complete) =
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(position) => (position + 1, true),
+                None => (available.len(), false),
+            };
+        line.extend_from_slice(&available[..consumed_size]);
+        reader.consume(consumed_size);
+        if line.len() > METRICS_MAX_LINE_LENGTH {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "request line is too long",
+            ));
+        }
+        if is_line_complete {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        (socket, client)
+    }
+
+    #[test]
+    fn test_request_lines_are_limited_in_length_and_time() {
+        let (socket, mut client) = create_test_connection();
+        let mut reader = BufReader::new(&socket);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(200);
+
+        client.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+        assert_eq!(
+            read_request_line(&mut reader, deadline).unwrap(),
+            "GET /metrics HTTP/1.1\r\n"
+        );
+
+        // the line never ends
+        client.write_all(b"Host: ").unwrap();
+        assert!(read_request_line(&mut reader, deadline).is_err());
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        client
+            .write_all(&vec![b'a'; METRICS_MAX_LINE_LENGTH + 1])
+            .unwrap();
+        assert_eq!(
+            read_request_line(&mut reader, deadline).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_metrics_are_formatted_in_text_format() {
+        let mut metrics = Metrics {
+            matches_created_total: 3,
+            ..Default::default()
+        };
+        metrics.add_match_wait_time(std::time::Duration::from_millis(1500));
+        let snapshot = MetricsSnapshot {
+            queued_players: BTreeMap::from([("ranked".to_string(), 2)]),
+            running_servers: 4,
+            idle_warm_servers: 1,
+            connected_sessions: 2,
+        };
+
+        let text = format_metrics(&metrics, &snapshot);
+        assert!(text.contains("# TYPE matchmaker_matches_created_total counter\n"));
+        assert!(text.contains("\nmatchmaker_matches_created_total 3\n"));
+        assert!(text.contains("\nmatchmaker_match_wait_seconds_sum 1.5\n"));
+        assert!(text.contains("\nmatchmaker_match_wait_seconds_count 1\n"));
+        assert!(text.contains("\nmatchmaker_queued_players{game_mode=\"ranked\"} 2\n"));
+        assert!(text.contains("\nmatchmaker_running_servers 4\n"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        assert_eq!(escape_label_value("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
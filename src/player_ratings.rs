@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::config::PlayerRatingsConfig;
+
+/// Result of one player in a finished match as reported by the dedicated server
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PlayerResult {
+    pub player_id: String,
+    // 1 for the winner, players with the same place are tied
+    pub place: u32,
+}
+
+/// Ratings of the players that played matches on the dedicated servers,
+/// updated with the Elo rating system from the results of the matches
+#[derive(Clone)]
+pub struct PlayerRatings {
+    ratings: HashMap<String, f32>,
+    // grows with every update, tells the newer copy of the ratings from the older one
+    version: u64,
+}
+
+impl PlayerRatings {
+    pub fn new() -> PlayerRatings {
+        PlayerRatings {
+            ratings: HashMap::new(),
+            version: 0,
+        }
+    }
+
+    /// Reads the ratings saved by the previous runs, no file means no ratings yet
+    pub fn load(file_path: &str) -> Result<PlayerRatings, String> {
+        let data = match std::fs::read_to_string(file_path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(PlayerRatings::new())
+            }
+            Err(error) => return Err(error.to_string()),
+        };
+
+        match serde_json::from_str(&data) {
+            Ok(ratings) => Ok(PlayerRatings {
+                ratings,
+                version: 0,
+            }),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    pub fn save(&self, file_path: &str) -> std::io::Result<()> {
+        let data = serde_json::to_string_pretty(&self.ratings).unwrap();
+        // write to a temporary file first to not lose the ratings if the matchmaker stops midway
+        let temporary_file_path = format!("{}.tmp", file_path);
+        std::fs::write(&temporary_file_path, data)?;
+        std::fs::rename(temporary_file_path, file_path)
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get(&self, player_id: &str) -> Option<f32> {
+        self.ratings.get(player_id).copied()
+    }
+
+    /// Updates the ratings of the players of a finished match, returns the rating changes
+    pub fn apply_match_results(
+        &mut self,
+        results: &[PlayerResult],
+        config: &PlayerRatingsConfig,
+    ) -> HashMap<String, f32> {
+        let ratings: Vec<f32> = results
+            .iter()
+            .map(|result| self.get(&result.player_id).unwrap_or(config.initial_rating))
+            .collect();
+        let places: Vec<u32> = results.iter().map(|result| result.place).collect();
+        let rating_changes = get_rating_changes(&ratings, &places, config.k_factor);
+
+        let mut changes_by_player = HashMap::new();
+        for ((result, rating), change) in results.iter().zip(ratings).zip(rating_changes) {
+            self.ratings
+                .insert(result.player_id.clone(), rating + change);
+            changes_by_player.insert(result.player_id.clone(), change);
+        }
+        self.version += 1;
+        changes_by_player
+    }
+}
+
+/// Multiplayer Elo: every player is compared with every other player of the match,
+/// and the sum of the changes is scaled so a match of any size moves the ratings as much
+/// as one game between two players
+fn get_rating_changes(ratings: &[f32], places: &[u32], k_factor: f32) -> Vec<f32> {
+    let players_count = ratings.len();
+    if players_count < 2 {
+        return vec![0.0; players_count];
+    }
+
+    (0..players_count)
+        .map(|player| {
+            let score_difference: f32 = (0..players_count)
+                .filter(|opponent| *opponent != player)
+                .map(|opponent| {
+                    let actual_score = match places[player].cmp(&places[opponent]) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    let expected_score =
+                        1.0 / (1.0 + 10f32.powf((ratings[opponent] - ratings[player]) / 400.0));
+                    actual_score - expected_score
+                })
+                .sum();
+            k_factor * score_difference / (players_count - 1) as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PlayerRatingsConfig {
+        PlayerRatingsConfig {
+            file_path: None,
+            initial_rating: 1500.0,
+            k_factor: 32.0,
+        }
+    }
+
+    fn result(player_id: &str, place: u32) -> PlayerResult {
+        PlayerResult {
+            player_id: player_id.to_string(),
+            place,
+        }
+    }
+
+    #[test]
+    fn test_winner_of_equal_players_gets_half_of_k_factor() {
+        let mut ratings = PlayerRatings::new();
+        let changes =
+            ratings.apply_match_results(&[result("first", 1), result("second", 2)], &config());
+        assert_eq!(changes["first"], 16.0);
+        assert_eq!(changes["second"], -16.0);
+        assert_eq!(ratings.get("first"), Some(1516.0));
+        assert_eq!(ratings.get("second"), Some(1484.0));
+    }
+
+    #[test]
+    fn test_tie_of_equal_players_changes_nothing() {
+        let changes = get_rating_changes(&[1500.0, 1500.0, 1500.0], &[1, 1, 1], 32.0);
+        assert_eq!(changes, vec![0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_underdog_win_changes_ratings_more() {
+        let changes = get_rating_changes(&[1300.0, 1700.0], &[1, 2], 32.0);
+        assert!(changes[0] > 16.0);
+        assert!((changes[0] + changes[1]).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_single_player_rating_does_not_change() {
+        assert_eq!(get_rating_changes(&[1500.0], &[1], 32.0), vec![0.0]);
+    }
+}
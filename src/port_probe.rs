@@ -0,0 +1,50 @@
+use std::fs;
+
+// the sockets are listed here without interfering with them, unlike binding the port to check it
+const UDP_SOCKET_TABLES: [&str; 2] = ["/proc/net/udp", "/proc/net/udp6"];
+
+/// Checks whether any process has a UDP socket bound to the given port
+pub fn is_udp_port_bound(port: u16) -> bool {
+    UDP_SOCKET_TABLES.iter().any(|table_path| {
+        match fs::read_to_string(table_path) {
+            Ok(table) => is_port_in_socket_table(&table, port),
+            // e.g. IPv6 is disabled
+            Err(_) => false,
+        }
+    })
+}
+
+fn is_port_in_socket_table(table: &str, port: u16) -> bool {
+    // the first line is the header, then each line is like
+    // "  12: 0100007F:1F40 00000000:0000 07 ..." where 0100007F:1F40 is the local address and port
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter_map(|local_address| local_address.rsplit(':').next())
+        .filter_map(|hex_port| u16::from_str_radix(hex_port, 16).ok())
+        .any(|bound_port| bound_port == port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bound_port_is_found_in_socket_table() {
+        let table = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+            \x20 12: 0100007F:1F40 00000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 31337 2 0000000000000000 0\n\
+            \x20 40: 00000000000000000000000000000000:1F41 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 31338 2 0000000000000000 0\n";
+        assert!(is_port_in_socket_table(table, 8000));
+        assert!(is_port_in_socket_table(table, 8001));
+        assert!(!is_port_in_socket_table(table, 8002));
+    }
+
+    #[test]
+    fn test_header_is_not_parsed_as_socket() {
+        assert!(!is_port_in_socket_table(
+            "   sl  local_address rem_address\n",
+            0
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// Keeps track of the ports given to dedicated servers from the moment they are spawned until
+/// they exit, because a freshly spawned server may not have opened its port yet
+pub struct PortReservations {
+    ports: RangeInclusive<u16>,
+    reserved_ports: HashSet<u16>,
+}
+
+impl PortReservations {
+    pub fn new(ports: RangeInclusive<u16>) -> PortReservations {
+        PortReservations {
+            ports,
+            reserved_ports: HashSet::new(),
+        }
+    }
+
+    /// Reserves the first port that is not reserved yet and for which is_port_free returns true
+    pub fn reserve_port<F>(&mut self, is_port_free: F) -> Option<u16>
+    where
+        F: Fn(u16) -> bool,
+    {
+        let port = self
+            .ports
+            .clone()
+            .find(|port| !self.reserved_ports.contains(port) && is_port_free(*port))?;
+        self.reserved_ports.insert(port);
+        Some(port)
+    }
+
+    pub fn release_port(&mut self, port: u16) {
+        self.reserved_ports.remove(&port);
+    }
+
+    /// Changes the range of ports that new reservations are made from,
+    /// ports that are already reserved stay reserved until they are released
+    pub fn set_port_range(&mut self, ports: RangeInclusive<u16>) {
+        self.ports = ports;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_port_is_not_given_twice() {
+        let mut reservations = PortReservations::new(8000..=8002);
+        assert_eq!(reservations.reserve_port(|_| true), Some(8000));
+        assert_eq!(reservations.reserve_port(|_| true), Some(8001));
+    }
+
+    #[test]
+    fn test_released_port_can_be_reserved_again() {
+        let mut reservations = PortReservations::new(8000..=8001);
+        assert_eq!(reservations.reserve_port(|_| true), Some(8000));
+        assert_eq!(reservations.reserve_port(|_| true), Some(8001));
+        assert_eq!(reservations.reserve_port(|_| true), None);
+
+        reservations.release_port(8000);
+        assert_eq!(reservations.reserve_port(|_| true), Some(8000));
+    }
+
+    #[test]
+    fn test_ports_that_are_not_free_are_skipped() {
+        let mut reservations = PortReservations::new(8000..=8002);
+        assert_eq!(reservations.reserve_port(|port| port != 8000), Some(8001));
+    }
+
+    #[test]
+    fn test_ports_reserved_before_range_change_stay_reserved() {
+        let mut reservations = PortReservations::new(8000..=8001);
+        assert_eq!(reservations.reserve_port(|_| true), Some(8000));
+
+        reservations.set_port_range(7999..=8000);
+        assert_eq!(reservations.reserve_port(|_| true), Some(7999));
+        assert_eq!(reservations.reserve_port(|_| true), None);
+    }
+}
@@ -0,0 +1,372 @@
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::request_error::RequestError;
+
+/// Whitespace-separated single line requests with free-form text responses
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// Newline-delimited json requests and responses
+pub const JSON_PROTOCOL_VERSION: u32 = 2;
+pub const LATEST_PROTOCOL_VERSION: u32 = JSON_PROTOCOL_VERSION;
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Without a version the client asks for the legacy version,
+    /// with a version the client asks to switch to the highest version both sides support
+    ProtocolVersion { version: Option<u32> },
+    /// Identifies the player, the matchmaker keeps one session per player
+    Hello { player_id: String, auth: String },
+    Connect {
+        game_mode: Option<String>,
+        rating: Option<f32>,
+    },
+    /// Leaves the queue, sent while waiting for a match
+    Cancel,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct RequestMessage {
+    // the client can set id to match responses to requests, it is copied to the response
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    InvalidRequest,
+    UnknownRequest,
+    UnsupportedProtocolVersion,
+    UnknownGameMode,
+    InvalidRating,
+    NoPortsAvailable,
+    ServerStartFailed,
+    ServerStopped,
+    ShuttingDown,
+    MatchmakingPaused,
+    ServerFull,
+    SessionActive,
+    SessionReplaced,
+    AuthenticationFailed,
+    AuthenticationRequired,
+    Cancelled,
+    QueueTimeout,
+    TooManyClients,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::UnknownRequest => "unknown_request",
+            ErrorCode::UnsupportedProtocolVersion => "unsupported_protocol_version",
+            ErrorCode::UnknownGameMode => "unknown_game_mode",
+            ErrorCode::InvalidRating => "invalid_rating",
+            ErrorCode::NoPortsAvailable => "no_ports_available",
+            ErrorCode::ServerStartFailed => "server_start_failed",
+            ErrorCode::ServerStopped => "server_stopped",
+            ErrorCode::ShuttingDown => "shutting_down",
+            ErrorCode::MatchmakingPaused => "matchmaking_paused",
+            ErrorCode::ServerFull => "server_full",
+            ErrorCode::SessionActive => "session_active",
+            ErrorCode::SessionReplaced => "session_replaced",
+            ErrorCode::AuthenticationFailed => "authentication_failed",
+            ErrorCode::AuthenticationRequired => "authentication_required",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::QueueTimeout => "queue_timeout",
+            ErrorCode::TooManyClients => "too_many_clients",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Everything a player needs to join the dedicated server of their match
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatchTicket {
+    pub host: String,
+    pub port: u16,
+    pub token: String,
+    // set if the match started without enough players, the server adds bots in their place
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub fill_with_bots: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    ProtocolVersion {
+        version: u32,
+    },
+    SessionStarted {
+        session_id: u64,
+        // set if the player is still waiting for a match from a previous connection,
+        // connecting to the same game mode again restores the place in the queue
+        #[serde(skip_serializing_if = "Option::is_none")]
+        queued_game_mode: Option<String>,
+    },
+    MatchFound(MatchTicket),
+    Cancelled,
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        retry_after_seconds: Option<u64>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ResponseMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub response: Response,
+}
+
+impl From<RequestError> for Response {
+    fn from(error: RequestError) -> Response {
+        Response::Error {
+            code: error.code(),
+            message: error.to_string(),
+            retry_after_seconds: error.retry_after_seconds(),
+        }
+    }
+}
+
+pub fn negotiate_protocol_version(requested_version: u32) -> Option<u32> {
+    if requested_version < LEGACY_PROTOCOL_VERSION {
+        return None;
+    }
+    Some(std::cmp::min(requested_version, LATEST_PROTOCOL_VERSION))
+}
+
+pub fn parse_legacy_request(words: &[String]) -> Result<Request, RequestError> {
+    match words {
+        [command] if command == "protocol-version" => {
+            Ok(Request::ProtocolVersion { version: None })
+        }
+        [command, version] if command == "protocol-version" => match version.parse::<u32>() {
+            Ok(version) => Ok(Request::ProtocolVersion {
+                version: Some(version),
+            }),
+            Err(_) => Err(RequestError::UnsupportedProtocolVersion),
+        },
+        [command, player_id, auth] if command == "hello" => Ok(Request::Hello {
+            player_id: player_id.clone(),
+            auth: auth.clone(),
+        }),
+        [command] if command == "cancel" => Ok(Request::Cancel),
+        [command] if command == "connect" => Ok(Request::Connect {
+            game_mode: None,
+            rating: None,
+        }),
+        [command, game_mode] if command == "connect" => Ok(Request::Connect {
+            game_mode: Some(game_mode.clone()),
+            rating: None,
+        }),
+        [command, game_mode, rating] if command == "connect" => match rating.parse::<f32>() {
+            Ok(rating) if rating.is_finite() => Ok(Request::Connect {
+                game_mode: Some(game_mode.clone()),
+                rating: Some(rating),
+            }),
+            _ => Err(RequestError::InvalidRating),
+        },
+        _ => Err(RequestError::UnknownRequest),
+    }
+}
+
+pub fn format_legacy_response(response: &Response) -> String {
+    match response {
+        Response::ProtocolVersion { version } => version.to_string(),
+        Response::SessionStarted {
+            session_id,
+            queued_game_mode: Some(queued_game_mode),
+        } => format!("session:{} queued:{}", session_id, queued_game_mode),
+        Response::SessionStarted { session_id, .. } => format!("session:{}", session_id),
+        // the port goes first for the clients that only know about the port
+        Response::MatchFound(ticket) if ticket.fill_with_bots => format!(
+            "port:{} host:{} token:{} fill_with_bots:true",
+            ticket.port, ticket.host, ticket.token
+        ),
+        Response::MatchFound(ticket) => format!(
+            "port:{} host:{} token:{}",
+            ticket.port, ticket.host, ticket.token
+        ),
+        Response::Cancelled => "cancelled".to_string(),
+        Response::Error {
+            code,
+            retry_after_seconds: Some(retry_after_seconds),
+            ..
+        } => format!(
+            "error:{} retry_after:{}",
+            code.as_str(),
+            retry_after_seconds
+        ),
+        Response::Error { code, .. } => format!("error:{}", code.as_str()),
+    }
+}
+
+pub fn parse_json_request(line: &str) -> Result<RequestMessage, RequestError> {
+    serde_json::from_str(line).map_err(|error| RequestError::InvalidRequest(error.to_string()))
+}
+
+pub fn format_json_response(response: &ResponseMessage) -> String {
+    serde_json::to_string(response).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_legacy_requests_are_parsed() {
+        assert_eq!(
+            parse_legacy_request(&words("protocol-version")).unwrap(),
+            Request::ProtocolVersion { version: None }
+        );
+        assert_eq!(
+            parse_legacy_request(&words("protocol-version 2")).unwrap(),
+            Request::ProtocolVersion { version: Some(2) }
+        );
+        assert_eq!(
+            parse_legacy_request(&words("connect ranked 1500")).unwrap(),
+            Request::Connect {
+                game_mode: Some("ranked".to_string()),
+                rating: Some(1500.0)
+            }
+        );
+        assert_eq!(
+            parse_legacy_request(&words("hello player1 secret")).unwrap(),
+            Request::Hello {
+                player_id: "player1".to_string(),
+                auth: "secret".to_string()
+            }
+        );
+        assert_eq!(
+            parse_legacy_request(&words("cancel")).unwrap(),
+            Request::Cancel
+        );
+        assert!(matches!(
+            parse_legacy_request(&words("connect ranked high")),
+            Err(RequestError::InvalidRating)
+        ));
+        assert!(matches!(
+            parse_legacy_request(&words("connect ranked 1500 now")),
+            Err(RequestError::UnknownRequest)
+        ));
+    }
+
+    #[test]
+    fn test_legacy_responses_keep_the_old_format() {
+        assert_eq!(
+            format_legacy_response(&Response::MatchFound(MatchTicket {
+                host: "10.0.0.5".to_string(),
+                port: 8001,
+                token: "abc".to_string(),
+                fill_with_bots: false,
+            })),
+            "port:8001 host:10.0.0.5 token:abc"
+        );
+        assert_eq!(
+            format_legacy_response(&Response::MatchFound(MatchTicket {
+                host: "10.0.0.5".to_string(),
+                port: 8001,
+                token: "abc".to_string(),
+                fill_with_bots: true,
+            })),
+            "port:8001 host:10.0.0.5 token:abc fill_with_bots:true"
+        );
+        assert_eq!(
+            format_legacy_response(&Response::ProtocolVersion { version: 1 }),
+            "1"
+        );
+        assert_eq!(
+            format_legacy_response(&Response::SessionStarted {
+                session_id: 4,
+                queued_game_mode: Some("ranked".to_string())
+            }),
+            "session:4 queued:ranked"
+        );
+    }
+
+    #[test]
+    fn test_legacy_error_responses_contain_error_code() {
+        assert_eq!(
+            format_legacy_response(&Response::from(RequestError::NoPortsAvailable)),
+            "error:no_ports_available"
+        );
+        assert_eq!(
+            format_legacy_response(&Response::from(RequestError::ServerFull {
+                retry_after_seconds: 30
+            })),
+            "error:server_full retry_after:30"
+        );
+    }
+
+    #[test]
+    fn test_json_request_is_parsed() {
+        let request =
+            parse_json_request(r#"{"id": 7, "type": "connect", "game_mode": "casual"}"#).unwrap();
+        assert_eq!(
+            request,
+            RequestMessage {
+                id: Some(7),
+                request: Request::Connect {
+                    game_mode: Some("casual".to_string()),
+                    rating: None
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_json_request_produces_error() {
+        let error = parse_json_request(r#"{"type": "dance"}"#).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn test_json_response_is_one_line() {
+        let response = ResponseMessage {
+            id: Some(3),
+            response: Response::from(RequestError::UnknownGameMode("duel".to_string())),
+        };
+        assert_eq!(
+            format_json_response(&response),
+            "{\"id\":3,\"type\":\"error\",\"code\":\"unknown_game_mode\",\"message\":\"unknown game mode 'duel'\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_match_ticket_fields_are_in_json_response() {
+        let response = ResponseMessage {
+            id: None,
+            response: Response::MatchFound(MatchTicket {
+                host: "10.0.0.5".to_string(),
+                port: 8001,
+                token: "abc".to_string(),
+                fill_with_bots: false,
+            }),
+        };
+        assert_eq!(
+            format_json_response(&response),
+            "{\"type\":\"match_found\",\"host\":\"10.0.0.5\",\"port\":8001,\"token\":\"abc\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_protocol_version_negotiation() {
+        assert_eq!(negotiate_protocol_version(0), None);
+        assert_eq!(negotiate_protocol_version(1), Some(1));
+        assert_eq!(negotiate_protocol_version(2), Some(2));
+        assert_eq!(negotiate_protocol_version(5), Some(LATEST_PROTOCOL_VERSION));
+    }
+}
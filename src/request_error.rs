@@ -0,0 +1,114 @@
+use std::fmt;
+
+use crate::protocol::ErrorCode;
+
+/// Everything that can go wrong while processing a client request
+#[derive(Debug)]
+pub enum RequestError {
+    InvalidRequest(String),
+    UnknownRequest,
+    UnsupportedProtocolVersion,
+    UnknownGameMode(String),
+    InvalidRating,
+    NoPortsAvailable,
+    ServerEnvironmentFailed(std::io::Error),
+    ServerSpawnFailed(std::io::Error),
+    ServerStopped,
+    ShuttingDown,
+    MatchmakingPaused,
+    ServerFull { retry_after_seconds: u64 },
+    SessionActive,
+    SessionReplaced,
+    AuthenticationFailed,
+    AuthenticationRequired,
+    Cancelled,
+    QueueTimeout,
+    TooManyClients,
+}
+
+impl RequestError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RequestError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            RequestError::UnknownRequest => ErrorCode::UnknownRequest,
+            RequestError::UnsupportedProtocolVersion => ErrorCode::UnsupportedProtocolVersion,
+            RequestError::UnknownGameMode(_) => ErrorCode::UnknownGameMode,
+            RequestError::InvalidRating => ErrorCode::InvalidRating,
+            RequestError::NoPortsAvailable => ErrorCode::NoPortsAvailable,
+            RequestError::ServerEnvironmentFailed(_) | RequestError::ServerSpawnFailed(_) => {
+                ErrorCode::ServerStartFailed
+            }
+            RequestError::ServerStopped => ErrorCode::ServerStopped,
+            RequestError::ShuttingDown => ErrorCode::ShuttingDown,
+            RequestError::MatchmakingPaused => ErrorCode::MatchmakingPaused,
+            RequestError::ServerFull { .. } => ErrorCode::ServerFull,
+            RequestError::SessionActive => ErrorCode::SessionActive,
+            RequestError::SessionReplaced => ErrorCode::SessionReplaced,
+            RequestError::AuthenticationFailed => ErrorCode::AuthenticationFailed,
+            RequestError::AuthenticationRequired => ErrorCode::AuthenticationRequired,
+            RequestError::Cancelled => ErrorCode::Cancelled,
+            RequestError::QueueTimeout => ErrorCode::QueueTimeout,
+            RequestError::TooManyClients => ErrorCode::TooManyClients,
+        }
+    }
+
+    /// When the client can try the request again, for the errors that are expected to go away
+    pub fn retry_after_seconds(&self) -> Option<u64> {
+        match self {
+            RequestError::ServerFull {
+                retry_after_seconds,
+            } => Some(*retry_after_seconds),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
+            RequestError::UnknownRequest => write!(f, "unknown request"),
+            RequestError::UnsupportedProtocolVersion => write!(f, "unsupported protocol version"),
+            RequestError::UnknownGameMode(name) => write!(f, "unknown game mode '{}'", name),
+            RequestError::InvalidRating => write!(f, "rating should be a number"),
+            RequestError::NoPortsAvailable => write!(f, "no ports available"),
+            RequestError::ServerEnvironmentFailed(error) => {
+                write!(f, "problem preparing dedicated server directory: {}", error)
+            }
+            RequestError::ServerSpawnFailed(error) => {
+                write!(f, "problem starting dedicated server: {}", error)
+            }
+            RequestError::ServerStopped => {
+                write!(f, "dedicated server stopped before the match started")
+            }
+            RequestError::ShuttingDown => write!(f, "matchmaker is shutting down"),
+            RequestError::MatchmakingPaused => write!(f, "matchmaking is paused"),
+            RequestError::ServerFull {
+                retry_after_seconds,
+            } => write!(
+                f,
+                "server full, retry after {} seconds",
+                retry_after_seconds
+            ),
+            RequestError::SessionActive => {
+                write!(f, "player already has a session on another connection")
+            }
+            RequestError::SessionReplaced => {
+                write!(
+                    f,
+                    "session was replaced by another connection of the player"
+                )
+            }
+            RequestError::AuthenticationFailed => write!(f, "authentication failed"),
+            RequestError::AuthenticationRequired => {
+                write!(
+                    f,
+                    "player should be identified with the hello request first"
+                )
+            }
+            RequestError::Cancelled => write!(f, "waiting for a match is cancelled"),
+            RequestError::QueueTimeout => write!(f, "no match was found in time"),
+            RequestError::TooManyClients => write!(f, "matchmaker is serving too many clients"),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::config::SharedConfig;
+use crate::logging::{log_error, log_info};
+use crate::player_ratings::{PlayerRatings, PlayerResult};
+use crate::server_registry::{ServerId, ServerInstance, ServerRegistry};
+use crate::MatchmakerState;
+
+// a server may have nothing to report for the whole match
+const SERVER_CALLBACK_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const MATCH_RESULTS_FILE_NAME: &str = "results.json";
+
+// the ratings are saved without holding the matchmaker state, so the copies of the ratings
+// from the matches that finished at the same time can come to be saved in any order
+static SAVED_RATINGS_VERSION: Mutex<u64> = Mutex::new(0);
+
+/// Messages that the dedicated servers send to the matchmaker
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerEvent {
+    // the first message on every connection, tells which server is connected
+    Hello { server_token: String },
+    Ready,
+    PlayerJoined { player_id: String },
+    PlayerLeft { player_id: String },
+    MatchFinished { results: Vec<PlayerResult> },
+    ShuttingDown,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerEventResponse {
+    Ok,
+    Error { message: String },
+}
+
+#[derive(Serialize)]
+struct MatchResultsRecord<'a> {
+    server_id: ServerId,
+    finished_at: String,
+    results: Vec<PlayerResultRecord<'a>>,
+}
+
+#[derive(Serialize)]
+struct PlayerResultRecord<'a> {
+    player_id: &'a str,
+    place: u32,
+    rating_change: Option<f32>,
+}
+
+/// Accepts connections from the dedicated servers on a separate thread,
+/// every connection gets its own thread
+pub fn start_server_callback_thread(
+    listener: TcpListener,
+    state: Arc<Mutex<MatchmakerState>>,
+    config: Arc<SharedConfig>,
+) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let state = state.clone();
+                    let config = config.clone();
+                    std::thread::spawn(move || {
+                        handle_server_connection(stream, &state, &config);
+                    });
+                }
+                Err(error) => {
+                    log_error!(
+                        "Problem accepting a dedicated server connection: {:?}",
+                        error
+                    );
+                }
+            }
+        }
+    });
+}
+
+fn handle_server_connection(
+    stream: TcpStream,
+    state: &Mutex<MatchmakerState>,
+    config: &SharedConfig,
+) {
+    let mut server_id: Option<ServerId> = None;
+
+    crate::serve_line_protocol(&stream, SERVER_CALLBACK_READ_TIMEOUT, |request_line| {
+        let result = parse_server_event(request_line)
+            .and_then(|event| process_server_event(event, &mut server_id, state, config));
+        let response = match result {
+            Ok(()) => ServerEventResponse::Ok,
+            Err(message) => {
+                log_error!("Problem processing dedicated server message: {}", message);
+                ServerEventResponse::Error { message }
+            }
+        };
+        serde_json::to_string(&response).unwrap() + "\n"
+    });
+}
+
+fn parse_server_event(line: &str) -> Result<ServerEvent, String> {
+    serde_json::from_str(line).map_err(|error| format!("invalid message: {}", error))
+}
+
+fn process_server_event(
+    event: ServerEvent,
+    server_id: &mut Option<ServerId>,
+    state: &Mutex<MatchmakerState>,
+    config: &SharedConfig,
+) -> Result<(), String> {
+    let mut state = state.lock().unwrap();
+
+    match event {
+        ServerEvent::Hello { server_token } => {
+            let id = match state.server_registry.find_by_server_token(&server_token) {
+                Some(id) => id,
+                None => return Err("there is no running server with this server token".to_string()),
+            };
+            log_info!("Dedicated server {} connected to the matchmaker", id);
+            *server_id = Some(id);
+        }
+        ServerEvent::Ready => {
+            let id = get_connected_server(*server_id, &mut state.server_registry)?.id;
+            log_info!("Dedicated server {} reported that it is ready", id);
+            state.server_registry.mark_ready(id);
+            // the players of a full match don't need to wait for the next readiness check
+            crate::hand_off_ready_matches(&mut state, &config.get());
+        }
+        ServerEvent::PlayerJoined { player_id } => {
+            let server = get_connected_server(*server_id, &mut state.server_registry)?;
+            let id = server.id;
+            log_info!("Player '{}' joined dedicated server {}", player_id, id);
+            server.connected_players.insert(player_id);
+        }
+        ServerEvent::PlayerLeft { player_id } => {
+            let server = get_connected_server(*server_id, &mut state.server_registry)?;
+            let id = server.id;
+            log_info!("Player '{}' left dedicated server {}", player_id, id);
+            server.connected_players.remove(&player_id);
+        }
+        ServerEvent::MatchFinished { results } => {
+            let server = get_connected_server(*server_id, &mut state.server_registry)?;
+            let id = server.id;
+            // the ratings are updated once per match
+            if server.has_match_results {
+                return Err(format!(
+                    "dedicated server {} already reported its match results",
+                    id
+                ));
+            }
+            validate_match_results(&results, &server.matched_players)?;
+            server.has_match_results = true;
+            let working_directory = server.working_directory.clone();
+            server.connected_players.clear();
+
+            let config = config.get();
+            let rating_changes = state
+                .player_ratings
+                .apply_match_results(&results, &config.player_ratings);
+            let ratings_to_save = config
+                .player_ratings
+                .file_path
+                .clone()
+                .map(|file_path| (file_path, state.player_ratings.clone()));
+            state.metrics.matches_finished_total += 1;
+            log_info!(
+                "Match on dedicated server {} finished with {} players",
+                id,
+                results.len()
+            );
+            // the files are written without blocking the matchmaking
+            drop(state);
+
+            if let Some((file_path, player_ratings)) = ratings_to_save {
+                save_player_ratings(&player_ratings, &file_path);
+            }
+
+            if let Err(error) =
+                write_match_results(id, &working_directory, &results, &rating_changes)
+            {
+                log_error!(
+                    "Problem writing match results of dedicated server {}: {:?}",
+                    id,
+                    error
+                );
+            }
+        }
+        ServerEvent::ShuttingDown => {
+            let id = get_connected_server(*server_id, &mut state.server_registry)?.id;
+            log_info!("Dedicated server {} reported that it is shutting down", id);
+            // the players waiting for a match on this server are moved to a new server
+            state.server_registry.mark_stopping(id);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the server that identified itself on the connection with the hello message
+fn get_connected_server(
+    server_id: Option<ServerId>,
+    server_registry: &mut ServerRegistry,
+) -> Result<&mut ServerInstance, String> {
+    let id = match server_id {
+        Some(id) => id,
+        None => return Err("hello is expected as the first message".to_string()),
+    };
+    match server_registry.get_mut(id) {
+        Some(server) => Ok(server),
+        None => Err(format!("dedicated server {} is not running anymore", id)),
+    }
+}
+
+/// Saves the copy of the ratings unless a newer copy is already saved
+fn save_player_ratings(player_ratings: &PlayerRatings, file_path: &str) {
+    let mut saved_version = SAVED_RATINGS_VERSION.lock().unwrap();
+    if player_ratings.version() <= *saved_version {
+        return;
+    }
+    match player_ratings.save(file_path) {
+        Ok(()) => *saved_version = player_ratings.version(),
+        Err(error) => log_error!(
+            "Problem saving player ratings to '{}': {:?}",
+            file_path,
+            error
+        ),
+    }
+}
+
+fn validate_match_results(
+    results: &[PlayerResult],
+    matched_players: &HashSet<String>,
+) -> Result<(), String> {
+    for (index, result) in results.iter().enumerate() {
+        if !matched_players.contains(&result.player_id) {
+            return Err(format!(
+                "player '{}' was not sent to this server",
+                result.player_id
+            ));
+        }
+        if results[..index]
+            .iter()
+            .any(|other| other.player_id == result.player_id)
+        {
+            return Err(format!(
+                "player '{}' is in the results more than once",
+                result.player_id
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the results next to the logs of the server, so they are archived together
+fn write_match_results(
+    server_id: ServerId,
+    working_directory: &str,
+    results: &[PlayerResult],
+    rating_changes: &HashMap<String, f32>,
+) -> std::io::Result<()> {
+    let record = MatchResultsRecord {
+        server_id,
+        finished_at: Utc::now().to_rfc3339(),
+        results: results
+            .iter()
+            .map(|result| PlayerResultRecord {
+                player_id: &result.player_id,
+                place: result.place,
+                rating_change: rating_changes.get(&result.player_id).copied(),
+            })
+            .collect(),
+    };
+    let record_json = serde_json::to_string_pretty(&record).unwrap();
+    std::fs::write(
+        Path::new(working_directory).join(MATCH_RESULTS_FILE_NAME),
+        record_json,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_events_are_parsed() {
+        assert_eq!(
+            parse_server_event(r#"{"type": "hello", "server_token": "abc"}"#),
+            Ok(ServerEvent::Hello {
+                server_token: "abc".to_string()
+            })
+        );
+        assert_eq!(
+            parse_server_event(
+                r#"{"type": "match_finished", "results": [{"player_id": "p1", "place": 1}]}"#
+            ),
+            Ok(ServerEvent::MatchFinished {
+                results: vec![PlayerResult {
+                    player_id: "p1".to_string(),
+                    place: 1
+                }]
+            })
+        );
+        assert!(parse_server_event(r#"{"type": "celebrate"}"#).is_err());
+    }
+
+    #[test]
+    fn test_results_with_duplicate_players_are_rejected() {
+        let results = vec![
+            PlayerResult {
+                player_id: "p1".to_string(),
+                place: 1,
+            },
+            PlayerResult {
+                player_id: "p1".to_string(),
+                place: 2,
+            },
+        ];
+        let matched_players = HashSet::from(["p1".to_string()]);
+        assert!(validate_match_results(&results, &matched_players).is_err());
+        assert!(validate_match_results(&results[..1], &matched_players).is_ok());
+    }
+
+    #[test]
+    fn test_older_copy_of_ratings_does_not_overwrite_newer_one() {
+        let file_path = std::env::temp_dir()
+            .join(format!(
+                "matchmaker_ratings_test_{}.json",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string();
+        let config = crate::config::get_default_config().player_ratings;
+        let results = vec![
+            PlayerResult {
+                player_id: "p1".to_string(),
+                place: 1,
+            },
+            PlayerResult {
+                player_id: "p2".to_string(),
+                place: 2,
+            },
+        ];
+        let mut player_ratings = PlayerRatings::new();
+        player_ratings.apply_match_results(&results, &config);
+        let older_ratings = player_ratings.clone();
+        player_ratings.apply_match_results(&results, &config);
+
+        save_player_ratings(&player_ratings, &file_path);
+        save_player_ratings(&older_ratings, &file_path);
+
+        let saved_ratings = PlayerRatings::load(&file_path).unwrap();
+        assert_eq!(saved_ratings.get("p1"), player_ratings.get("p1"));
+        let _ = std::fs::remove_file(&file_path);
+    }
+
+    #[test]
+    fn test_results_of_players_from_other_servers_are_rejected() {
+        let results = vec![PlayerResult {
+            player_id: "p2".to_string(),
+            place: 1,
+        }];
+        let matched_players = HashSet::from(["p1".to_string()]);
+        assert_eq!(
+            validate_match_results(&results, &matched_players),
+            Err("player 'p2' was not sent to this server".to_string())
+        );
+    }
+}
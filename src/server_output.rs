@@ -0,0 +1,303 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::logging::log_error;
+use crate::server_registry::{ServerInstance, ServerState};
+
+pub const STDOUT_FILE_NAME: &str = "stdout.log";
+pub const STDERR_FILE_NAME: &str = "stderr.log";
+pub const EXIT_METADATA_FILE_NAME: &str = "exit.json";
+pub const MATCH_INFO_FILE_NAME: &str = "match.json";
+
+/// Files in the working directory of a dedicated server that receive its output
+pub struct ServerOutput {
+    stdout_file: fs::File,
+    stderr_file: fs::File,
+    max_size: Option<u64>,
+    ready_line: Option<String>,
+}
+
+/// Set by the thread that copies the output of the server once the server prints its ready line
+pub type ReadyFlag = Arc<AtomicBool>;
+
+// longer lines are checked only by their beginning
+const MAX_WATCHED_LINE_LENGTH: usize = 4096;
+
+/// Looks for the line that the server prints when it is ready to accept players
+struct ReadyLineWatcher {
+    ready_line: String,
+    current_line: Vec<u8>,
+    ready_flag: ReadyFlag,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ExitMetadata {
+    server_id: u64,
+    pid: u32,
+    port: u16,
+    started_at: String,
+    exited_at: String,
+    state: &'static str,
+    // set if the server exited by itself
+    exit_code: Option<i32>,
+    // set if the server was terminated by a signal
+    signal: Option<i32>,
+}
+
+/// What the dedicated server needs to know about its match besides the join token
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MatchInfo {
+    pub players_count: usize,
+    // set if the matchmaker didn't find enough players in time
+    pub fill_with_bots: bool,
+    pub bots_count: usize,
+}
+
+impl ServerOutput {
+    /// ready_line is the text that the server prints to its stdout when it is ready
+    pub fn create(
+        working_directory: &str,
+        max_size: Option<u64>,
+        ready_line: Option<&str>,
+    ) -> std::io::Result<ServerOutput> {
+        Ok(ServerOutput {
+            stdout_file: fs::File::create(Path::new(working_directory).join(STDOUT_FILE_NAME))?,
+            stderr_file: fs::File::create(Path::new(working_directory).join(STDERR_FILE_NAME))?,
+            max_size,
+            ready_line: ready_line.map(|ready_line| ready_line.to_string()),
+        })
+    }
+
+    fn is_piped(&self) -> bool {
+        self.max_size.is_some() || self.ready_line.is_some()
+    }
+
+    /// Returns what should be given to the process as its stdout and stderr
+    pub fn get_stdio(&self) -> std::io::Result<(Stdio, Stdio)> {
+        if self.is_piped() {
+            Ok((Stdio::piped(), Stdio::piped()))
+        } else {
+            // when the output doesn't need to be looked at, the server writes to the files
+            // directly and keeps doing that even if the matchmaker exits
+            Ok((
+                Stdio::from(self.stdout_file.try_clone()?),
+                Stdio::from(self.stderr_file.try_clone()?),
+            ))
+        }
+    }
+
+    /// Starts copying the output of the spawned process to the files if it goes through pipes,
+    /// returns the flag that is set when the server prints its ready line
+    pub fn attach(self, process: &mut Child) -> Option<ReadyFlag> {
+        if !self.is_piped() {
+            return None;
+        }
+
+        let ready_line_watcher = self.ready_line.map(|ready_line| ReadyLineWatcher {
+            ready_line,
+            current_line: Vec::new(),
+            ready_flag: Arc::new(AtomicBool::new(false)),
+        });
+        let ready_flag = ready_line_watcher
+            .as_ref()
+            .map(|watcher| watcher.ready_flag.clone());
+
+        if let Some(stdout) = process.stdout.take() {
+            start_copy_thread(stdout, self.stdout_file, self.max_size, ready_line_watcher);
+        }
+        if let Some(stderr) = process.stderr.take() {
+            start_copy_thread(stderr, self.stderr_file, self.max_size, None);
+        }
+        ready_flag
+    }
+}
+
+impl ReadyLineWatcher {
+    fn feed(&mut self, data: &[u8]) {
+        if self.ready_flag.load(Ordering::Relaxed) {
+            return;
+        }
+
+        for byte in data {
+            if *byte == b'\n' {
+                if String::from_utf8_lossy(&self.current_line).contains(&self.ready_line) {
+                    self.ready_flag.store(true, Ordering::Relaxed);
+                    return;
+                }
+                self.current_line.clear();
+            } else if self.current_line.len() < MAX_WATCHED_LINE_LENGTH {
+                self.current_line.push(*byte);
+            }
+        }
+    }
+}
+
+fn start_copy_thread<R>(
+    reader: R,
+    file: fs::File,
+    max_size: Option<u64>,
+    ready_line_watcher: Option<ReadyLineWatcher>,
+) where
+    R: Read + Send + 'static,
+{
+    std::thread::spawn(move || {
+        if let Err(error) = copy_with_limit(reader, file, max_size, ready_line_watcher) {
+            log_error!("Problem writing dedicated server output: {:?}", error);
+        }
+    });
+}
+
+/// Copies everything from the reader until it is closed, but writes only the first max_size bytes,
+/// the rest is read and dropped so the process doesn't get blocked on a full pipe
+fn copy_with_limit<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    max_size: Option<u64>,
+    mut ready_line_watcher: Option<ReadyLineWatcher>,
+) -> std::io::Result<()> {
+    let max_size = max_size.unwrap_or(u64::MAX);
+    let mut written_size: u64 = 0;
+    let mut is_truncated = false;
+    let mut buffer = [0; 8192];
+    loop {
+        let read_size = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read_size) => read_size,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+
+        if let Some(ready_line_watcher) = &mut ready_line_watcher {
+            ready_line_watcher.feed(&buffer[..read_size]);
+        }
+
+        let size_to_write = std::cmp::min(read_size as u64, max_size - written_size) as usize;
+        writer.write_all(&buffer[..size_to_write])?;
+        written_size += size_to_write as u64;
+
+        if size_to_write < read_size && !is_truncated {
+            writeln!(writer, "\n[output truncated after {} bytes]", max_size)?;
+            is_truncated = true;
+        }
+    }
+    Ok(())
+}
+
+/// Writes how the server exited into a file in its working directory
+pub fn write_exit_metadata(server: &ServerInstance) -> std::io::Result<()> {
+    let metadata = get_exit_metadata(server, server.exit_status, Utc::now());
+    let metadata_json = serde_json::to_string_pretty(&metadata).unwrap();
+    fs::write(
+        Path::new(&server.working_directory).join(EXIT_METADATA_FILE_NAME),
+        metadata_json,
+    )
+}
+
+/// Whether the exit metadata in the working directory says that the server crashed
+pub fn is_crashed_instance(working_directory: &Path) -> bool {
+    let metadata_json = match fs::read_to_string(working_directory.join(EXIT_METADATA_FILE_NAME)) {
+        Ok(metadata_json) => metadata_json,
+        Err(_) => return false,
+    };
+    match serde_json::from_str::<serde_json::Value>(&metadata_json) {
+        Ok(metadata) => metadata["state"] == "crashed",
+        Err(_) => false,
+    }
+}
+
+/// Writes the match of the server into a file in its working directory,
+/// the file is written before the players get their tickets
+pub fn write_match_info(working_directory: &str, match_info: &MatchInfo) -> std::io::Result<()> {
+    let match_info_json = serde_json::to_string_pretty(match_info).unwrap();
+    fs::write(
+        Path::new(working_directory).join(MATCH_INFO_FILE_NAME),
+        match_info_json,
+    )
+}
+
+fn get_exit_metadata(
+    server: &ServerInstance,
+    exit_status: Option<ExitStatus>,
+    exited_at: DateTime<Utc>,
+) -> ExitMetadata {
+    ExitMetadata {
+        server_id: server.id,
+        pid: server.pid,
+        port: server.port,
+        started_at: server.start_time.to_rfc3339(),
+        exited_at: exited_at.to_rfc3339(),
+        state: match server.state {
+            ServerState::Running => "running",
+            ServerState::Stopping => "stopping",
+            ServerState::Stopped => "stopped",
+            ServerState::Finished => "finished",
+            ServerState::Crashed(_) => "crashed",
+        },
+        exit_code: exit_status.and_then(|status| status.code()),
+        signal: exit_status.and_then(|status| status.signal()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_is_copied_up_to_the_limit() {
+        let mut output = Vec::new();
+        copy_with_limit(&b"0123456789"[..], &mut output, Some(4), None).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0123\n[output truncated after 4 bytes]\n"
+        );
+
+        let mut output = Vec::new();
+        copy_with_limit(&b"0123"[..], &mut output, Some(4), None).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "0123");
+    }
+
+    #[test]
+    fn test_ready_line_is_found_in_output() {
+        let mut watcher = ReadyLineWatcher {
+            ready_line: "Server is ready".to_string(),
+            current_line: Vec::new(),
+            ready_flag: Arc::new(AtomicBool::new(false)),
+        };
+        watcher.feed(b"Loading map\n[12:00] Server is");
+        assert!(!watcher.ready_flag.load(Ordering::Relaxed));
+        watcher.feed(b" ready on port 8000\n");
+        assert!(watcher.ready_flag.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_exit_metadata_has_exit_code_or_signal() {
+        let process = std::process::Command::new("true").spawn().unwrap();
+        let mut registry = crate::server_registry::ServerRegistry::new();
+        let id = registry.register(
+            process,
+            8000,
+            "token".to_string(),
+            "server_token".to_string(),
+            "dir".to_string(),
+            None,
+        );
+        let server = registry.get(id).unwrap();
+        let exited_at = Utc::now();
+
+        let metadata = get_exit_metadata(server, Some(ExitStatus::from_raw(3 << 8)), exited_at);
+        assert_eq!(metadata.exit_code, Some(3));
+        assert_eq!(metadata.signal, None);
+
+        let metadata = get_exit_metadata(server, Some(ExitStatus::from_raw(9)), exited_at);
+        assert_eq!(metadata.exit_code, None);
+        assert_eq!(metadata.signal, Some(9));
+    }
+}
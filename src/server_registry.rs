@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::process::{Child, ExitStatus};
+
+use chrono::{DateTime, Utc};
+
+use crate::logging::log_error;
+use crate::server_output::ReadyFlag;
+
+pub type ServerId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerState {
+    Running,
+    // the matchmaker asked the server to stop or the server reported that it is shutting down,
+    // but it hasn't exited yet
+    Stopping,
+    Stopped,
+    Finished,
+    Crashed(ExitStatus),
+}
+
+/// A dedicated server process spawned by the matchmaker
+pub struct ServerInstance {
+    pub id: ServerId,
+    pub pid: u32,
+    pub port: u16,
+    // secret that players need to present to the server to join the match
+    pub join_token: String,
+    // secret that only the server knows, it identifies the server when it reports to the matchmaker
+    pub server_token: String,
+    pub working_directory: String,
+    pub start_time: DateTime<Utc>,
+    pub state: ServerState,
+    // set once the server has exited
+    pub exit_status: Option<ExitStatus>,
+    // set once the server is ready to accept players
+    pub is_ready: bool,
+    // set by the thread that watches the output of the server if the server reports its readiness there
+    pub ready_flag: Option<ReadyFlag>,
+    // players that the server reported as connected to it
+    pub connected_players: HashSet<String>,
+    // players with sessions that got the ticket to this server
+    pub matched_players: HashSet<String>,
+    // set once the server reported the results of its match
+    pub has_match_results: bool,
+    process: Child,
+}
+
+/// Owns all the dedicated server processes that are currently running, so we can tell whether
+/// a server is still alive and collect the exit status of the ones that are not.
+pub struct ServerRegistry {
+    next_id: ServerId,
+    servers: HashMap<ServerId, ServerInstance>,
+}
+
+impl ServerRegistry {
+    pub fn new() -> ServerRegistry {
+        ServerRegistry {
+            next_id: 1,
+            servers: HashMap::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        process: Child,
+        port: u16,
+        join_token: String,
+        server_token: String,
+        working_directory: String,
+        ready_flag: Option<ReadyFlag>,
+    ) -> ServerId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.servers.insert(
+            id,
+            ServerInstance {
+                id,
+                pid: process.id(),
+                port,
+                join_token,
+                server_token,
+                working_directory,
+                start_time: Utc::now(),
+                state: ServerState::Running,
+                exit_status: None,
+                is_ready: false,
+                ready_flag,
+                connected_players: HashSet::new(),
+                matched_players: HashSet::new(),
+                has_match_results: false,
+                process,
+            },
+        );
+
+        id
+    }
+
+    pub fn get(&self, id: ServerId) -> Option<&ServerInstance> {
+        self.servers.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: ServerId) -> Option<&mut ServerInstance> {
+        self.servers.get_mut(&id)
+    }
+
+    pub fn find_by_server_token(&self, server_token: &str) -> Option<ServerId> {
+        self.servers
+            .values()
+            .find(|server| server.server_token == server_token)
+            .map(|server| server.id)
+    }
+
+    pub fn is_running(&self, id: ServerId) -> bool {
+        match self.servers.get(&id) {
+            Some(server) => server.state == ServerState::Running,
+            None => false,
+        }
+    }
+
+    pub fn running_servers_count(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn get_server_ids(&self) -> Vec<ServerId> {
+        self.servers.keys().copied().collect()
+    }
+
+    /// Returns all the servers that haven't exited yet, sorted by id
+    pub fn get_servers(&self) -> Vec<&ServerInstance> {
+        let mut servers: Vec<&ServerInstance> = self.servers.values().collect();
+        servers.sort_by_key(|server| server.id);
+        servers
+    }
+
+    pub fn is_ready(&self, id: ServerId) -> bool {
+        match self.servers.get(&id) {
+            Some(server) => server.is_ready && server.state == ServerState::Running,
+            None => false,
+        }
+    }
+
+    pub fn mark_ready(&mut self, id: ServerId) {
+        if let Some(server) = self.servers.get_mut(&id) {
+            server.is_ready = true;
+        }
+    }
+
+    /// Marks a server that is going to exit by itself, so no more players are sent to it
+    pub fn mark_stopping(&mut self, id: ServerId) {
+        if let Some(server) = self.servers.get_mut(&id) {
+            server.state = ServerState::Stopping;
+        }
+    }
+
+    /// Asks the server to exit by sending SIGTERM, the server can still finish its work
+    pub fn stop_server(&mut self, id: ServerId) {
+        self.send_signal(id, libc::SIGTERM);
+    }
+
+    /// Terminates the server right away by sending SIGKILL
+    pub fn kill_server(&mut self, id: ServerId) {
+        self.send_signal(id, libc::SIGKILL);
+    }
+
+    fn send_signal(&mut self, id: ServerId, signal: libc::c_int) {
+        if let Some(server) = self.servers.get_mut(&id) {
+            // every server has its own process group, the signal is sent to the whole group
+            // to also reach the processes that the server may have started
+            let result = unsafe { libc::kill(-(server.pid as libc::pid_t), signal) };
+            if result != 0 {
+                log_error!(
+                    "Problem sending signal {} to dedicated server {} (pid {}): {:?}",
+                    signal,
+                    id,
+                    server.pid,
+                    std::io::Error::last_os_error()
+                );
+            }
+            server.state = ServerState::Stopping;
+        }
+    }
+
+    /// Checks all the servers without blocking, removes the ones that have exited
+    /// and returns them with their final state
+    pub fn reap_exited_servers(&mut self) -> Vec<ServerInstance> {
+        let mut exited_ids = Vec::new();
+        for (id, server) in self.servers.iter_mut() {
+            match server.process.try_wait() {
+                Ok(Some(status)) => {
+                    server.exit_status = Some(status);
+                    server.state = if server.state == ServerState::Stopping {
+                        ServerState::Stopped
+                    } else if status.success() {
+                        ServerState::Finished
+                    } else {
+                        ServerState::Crashed(status)
+                    };
+                    exited_ids.push(*id);
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    log_error!(
+                        "Problem checking status of dedicated server {} (pid {}): {:?}",
+                        id,
+                        server.pid,
+                        error
+                    );
+                }
+            }
+        }
+
+        exited_ids
+            .iter()
+            .filter_map(|id| self.servers.remove(id))
+            .collect()
+    }
+}
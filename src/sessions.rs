@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::request_error::RequestError;
+
+pub type SessionId = u64;
+
+// long enough for any reasonable account id, short enough to keep the logs readable
+const MAX_PLAYER_ID_LENGTH: usize = 64;
+
+/// The player that identified itself on a connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: SessionId,
+    pub player_id: String,
+}
+
+struct PlayerSession {
+    session_id: SessionId,
+    // the secret the session was opened with, the player has to present it again to reconnect
+    auth: String,
+    is_connected: bool,
+}
+
+/// Keeps the latest session of every player, so a player has only one place in the queue
+/// no matter how many connections it opens
+pub struct SessionRegistry {
+    next_session_id: SessionId,
+    sessions: HashMap<String, PlayerSession>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> SessionRegistry {
+        SessionRegistry {
+            next_session_id: 1,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Opens a new session for the player. The previous session of the player is replaced
+    /// if it was opened with the same auth, if it is not connected and not waiting for a match,
+    /// or if the identity of the player was verified by the authenticator.
+    pub fn open_session(
+        &mut self,
+        player_id: &str,
+        auth: &str,
+        is_queued: bool,
+        is_identity_verified: bool,
+    ) -> Result<Session, RequestError> {
+        if let Some(previous_session) = self.sessions.get(player_id) {
+            let is_in_use = previous_session.is_connected || is_queued;
+            if is_in_use && previous_session.auth != auth && !is_identity_verified {
+                return Err(RequestError::SessionActive);
+            }
+        }
+
+        let session_id = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(
+            player_id.to_string(),
+            PlayerSession {
+                session_id,
+                auth: auth.to_string(),
+                is_connected: true,
+            },
+        );
+        Ok(Session {
+            id: session_id,
+            player_id: player_id.to_string(),
+        })
+    }
+
+    /// Checks that the session wasn't replaced by a newer one
+    pub fn is_current(&self, session: &Session) -> bool {
+        match self.sessions.get(&session.player_id) {
+            Some(player_session) => player_session.session_id == session.id,
+            None => false,
+        }
+    }
+
+    /// Called when the connection of the session is closed,
+    /// the session is kept for a while so the player can reconnect
+    pub fn mark_disconnected(&mut self, session: &Session) {
+        if let Some(player_session) = self.sessions.get_mut(&session.player_id) {
+            if player_session.session_id == session.id {
+                player_session.is_connected = false;
+            }
+        }
+    }
+
+    /// Forgets the sessions of the players that disconnected and aren't waiting for a match
+    pub fn remove_stale_sessions<F>(&mut self, is_queued: F)
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.sessions.retain(|player_id, player_session| {
+            player_session.is_connected || is_queued(player_id)
+        });
+    }
+
+    pub fn connected_sessions_count(&self) -> usize {
+        self.sessions
+            .values()
+            .filter(|player_session| player_session.is_connected)
+            .count()
+    }
+}
+
+pub fn validate_player_id(player_id: &str) -> Result<(), RequestError> {
+    if player_id.is_empty() || player_id.len() > MAX_PLAYER_ID_LENGTH {
+        return Err(RequestError::InvalidRequest(format!(
+            "player id should be from 1 to {} characters long",
+            MAX_PLAYER_ID_LENGTH
+        )));
+    }
+    // player ids are written to the legacy protocol responses and to the logs as is
+    if player_id
+        .chars()
+        .any(|character| character.is_whitespace() || character.is_control())
+    {
+        return Err(RequestError::InvalidRequest(
+            "player id should not contain whitespace".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_with_same_auth_replaces_previous_one() {
+        let mut sessions = SessionRegistry::new();
+        let first_session = sessions
+            .open_session("player", "secret", false, false)
+            .unwrap();
+        let second_session = sessions
+            .open_session("player", "secret", true, false)
+            .unwrap();
+
+        assert!(!sessions.is_current(&first_session));
+        assert!(sessions.is_current(&second_session));
+        assert_eq!(sessions.connected_sessions_count(), 1);
+    }
+
+    #[test]
+    fn test_session_in_use_is_not_taken_with_other_auth() {
+        let mut sessions = SessionRegistry::new();
+        let session = sessions
+            .open_session("player", "secret", false, false)
+            .unwrap();
+        assert!(matches!(
+            sessions.open_session("player", "guess", false, false),
+            Err(RequestError::SessionActive)
+        ));
+
+        // the player is still waiting for a match after disconnecting
+        sessions.mark_disconnected(&session);
+        assert!(matches!(
+            sessions.open_session("player", "guess", true, false),
+            Err(RequestError::SessionActive)
+        ));
+        assert!(sessions
+            .open_session("player", "guess", false, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_verified_player_can_replace_session_with_new_auth() {
+        let mut sessions = SessionRegistry::new();
+        sessions
+            .open_session("player", "ticket1", true, true)
+            .unwrap();
+        assert!(sessions
+            .open_session("player", "ticket2", true, true)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_disconnected_sessions_are_removed_once_not_queued() {
+        let mut sessions = SessionRegistry::new();
+        let first_session = sessions
+            .open_session("first", "secret", false, false)
+            .unwrap();
+        let second_session = sessions
+            .open_session("second", "secret", false, false)
+            .unwrap();
+        sessions.mark_disconnected(&first_session);
+        sessions.mark_disconnected(&second_session);
+
+        sessions.remove_stale_sessions(|player_id| player_id == "second");
+        assert!(!sessions.is_current(&first_session));
+        assert!(sessions.is_current(&second_session));
+    }
+
+    #[test]
+    fn test_invalid_player_ids_are_rejected() {
+        assert!(validate_player_id("player1").is_ok());
+        assert!(validate_player_id("").is_err());
+        assert!(validate_player_id("two words").is_err());
+        assert!(validate_player_id(&"x".repeat(MAX_PLAYER_ID_LENGTH + 1)).is_err());
+    }
+}
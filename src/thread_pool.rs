@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::logging::log_error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Fixed amount of worker threads that execute jobs in the order they were submitted.
+/// If all the workers are busy, new jobs wait until one of the workers becomes free.
+pub struct ThreadPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+    // jobs that are running or waiting for a worker
+    active_jobs_count: Arc<AtomicUsize>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let active_jobs_count = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let active_jobs_count = active_jobs_count.clone();
+                thread::spawn(move || loop {
+                    // the lock is released as soon as we get the job
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => {
+                            job();
+                            active_jobs_count.fetch_sub(1, Ordering::SeqCst);
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+            active_jobs_count,
+        }
+    }
+
+    /// Whether a job that is given to the pool now starts right away
+    pub fn has_idle_worker(&self) -> bool {
+        self.active_jobs_count.load(Ordering::SeqCst) < self.workers.len()
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.active_jobs_count.fetch_add(1, Ordering::SeqCst);
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // closing the channel makes the workers exit after finishing their current jobs
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap_or_else(|_| {
+                log_error!("A worker thread panicked");
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busy_workers_are_not_idle() {
+        let pool = ThreadPool::new(1);
+        assert!(pool.has_idle_worker());
+
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let (done_sender, done_receiver) = mpsc::channel();
+        pool.execute(move || {
+            let _ = release_receiver.recv();
+            done_sender.send(()).unwrap();
+        });
+        assert!(!pool.has_idle_worker());
+
+        release_sender.send(()).unwrap();
+        done_receiver.recv().unwrap();
+        // the worker becomes idle right after the job returns
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !pool.has_idle_worker() {
+            assert!(std::time::Instant::now() < deadline);
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+use crate::config::TlsConfig;
+
+/// Certificate of the matchmaker listener that can be replaced while the matchmaker is running,
+/// the connections that are already open keep the certificate they started with
+#[derive(Debug)]
+pub struct CertificateStore {
+    certified_key: RwLock<Arc<CertifiedKey>>,
+    // modification times of the certificate and the key files that were loaded
+    loaded_files_modified_at: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl CertificateStore {
+    pub fn load(config: &TlsConfig) -> Result<CertificateStore, String> {
+        let files_modified_at = get_files_modified_at(config);
+        Ok(CertificateStore {
+            certified_key: RwLock::new(Arc::new(load_certified_key(config)?)),
+            loaded_files_modified_at: Mutex::new(files_modified_at),
+        })
+    }
+
+    /// Replaces the certificate with the one from the files,
+    /// the current certificate is kept if the files can't be loaded
+    pub fn reload(&self, config: &TlsConfig) -> Result<(), String> {
+        let files_modified_at = get_files_modified_at(config);
+        let certified_key = load_certified_key(config)?;
+        *self.certified_key.write().unwrap() = Arc::new(certified_key);
+        *self.loaded_files_modified_at.lock().unwrap() = files_modified_at;
+        Ok(())
+    }
+
+    /// Reloads the certificate if the certificate or the key file was replaced since the last load
+    pub fn reload_if_changed(&self, config: &TlsConfig) -> Result<bool, String> {
+        let files_modified_at = get_files_modified_at(config);
+        {
+            let mut loaded_files_modified_at = self.loaded_files_modified_at.lock().unwrap();
+            if *loaded_files_modified_at == files_modified_at {
+                return Ok(false);
+            }
+            // a broken file is reported once, not on every check
+            *loaded_files_modified_at = files_modified_at;
+        }
+        self.reload(config)?;
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertificateStore {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().unwrap().clone())
+    }
+}
+
+pub fn create_server_config(certificates: Arc<CertificateStore>) -> Arc<ServerConfig> {
+    Arc::new(
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(certificates),
+    )
+}
+
+fn load_certified_key(config: &TlsConfig) -> Result<CertifiedKey, String> {
+    let mut certificate_reader =
+        BufReader::new(File::open(&config.certificate_path).map_err(|error| {
+            format!("problem opening '{}': {}", config.certificate_path, error)
+        })?);
+    let certificate_chain = rustls_pemfile::certs(&mut certificate_reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| format!("problem reading '{}': {}", config.certificate_path, error))?;
+    if certificate_chain.is_empty() {
+        return Err(format!("no certificates in '{}'", config.certificate_path));
+    }
+
+    let mut private_key_reader =
+        BufReader::new(File::open(&config.private_key_path).map_err(|error| {
+            format!("problem opening '{}': {}", config.private_key_path, error)
+        })?);
+    let private_key = rustls_pemfile::private_key(&mut private_key_reader)
+        .map_err(|error| format!("problem reading '{}': {}", config.private_key_path, error))?
+        .ok_or_else(|| format!("no private key in '{}'", config.private_key_path))?;
+
+    CertifiedKey::from_der(
+        certificate_chain,
+        private_key,
+        &rustls::crypto::ring::default_provider(),
+    )
+    .map_err(|error| format!("certificate and private key can't be used: {}", error))
+}
+
+fn get_files_modified_at(config: &TlsConfig) -> (Option<SystemTime>, Option<SystemTime>) {
+    let get_modified_at = |path: &str| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    (
+        get_modified_at(&config.certificate_path),
+        get_modified_at(&config.private_key_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_or_empty_files_are_reported() {
+        let directory = std::env::temp_dir().join(format!("tls_test_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let empty_file_path = directory.join("empty.pem").to_string_lossy().to_string();
+        std::fs::write(&empty_file_path, "").unwrap();
+
+        let config = TlsConfig {
+            certificate_path: empty_file_path.clone(),
+            private_key_path: empty_file_path.clone(),
+        };
+        assert_eq!(
+            CertificateStore::load(&config).unwrap_err(),
+            format!("no certificates in '{}'", empty_file_path)
+        );
+
+        let config = TlsConfig {
+            certificate_path: directory.join("missing.pem").to_string_lossy().to_string(),
+            private_key_path: empty_file_path,
+        };
+        assert!(CertificateStore::load(&config)
+            .unwrap_err()
+            .starts_with("problem opening"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::server_registry::ServerId;
+
+/// Dedicated servers that are started before anybody asks for a match,
+/// so the players of a new match don't wait for the server to start
+pub struct WarmPool {
+    // by game mode name, the oldest server first
+    idle_servers: HashMap<String, Vec<IdleServer>>,
+}
+
+struct IdleServer {
+    server_id: ServerId,
+    idle_since: Instant,
+}
+
+impl WarmPool {
+    pub fn new() -> WarmPool {
+        WarmPool {
+            idle_servers: HashMap::new(),
+        }
+    }
+
+    pub fn add_server(&mut self, game_mode_name: &str, server_id: ServerId) {
+        self.idle_servers
+            .entry(game_mode_name.to_string())
+            .or_default()
+            .push(IdleServer {
+                server_id,
+                idle_since: Instant::now(),
+            });
+    }
+
+    /// Takes a server for a new match, a server that is already ready is preferred,
+    /// otherwise the one that was started first
+    pub fn take_server<F>(&mut self, game_mode_name: &str, is_ready: F) -> Option<ServerId>
+    where
+        F: Fn(ServerId) -> bool,
+    {
+        let servers = self.idle_servers.get_mut(game_mode_name)?;
+        if servers.is_empty() {
+            return None;
+        }
+        let index = servers
+            .iter()
+            .position(|server| is_ready(server.server_id))
+            .unwrap_or(0);
+        Some(servers.remove(index).server_id)
+    }
+
+    pub fn get_idle_servers_count(&self, game_mode_name: &str) -> usize {
+        self.idle_servers
+            .get(game_mode_name)
+            .map_or(0, |servers| servers.len())
+    }
+
+    pub fn get_total_idle_servers_count(&self) -> usize {
+        self.idle_servers
+            .values()
+            .map(|servers| servers.len())
+            .sum()
+    }
+
+    /// Forgets the servers that exited or are being stopped
+    pub fn remove_stopped_servers<F>(&mut self, is_running: F)
+    where
+        F: Fn(ServerId) -> bool,
+    {
+        for servers in self.idle_servers.values_mut() {
+            servers.retain(|server| is_running(server.server_id));
+        }
+    }
+
+    /// Removes and returns the servers that were idle for longer than idle_timeout
+    pub fn take_expired_servers(&mut self, idle_timeout: Duration) -> Vec<ServerId> {
+        let mut expired_servers = Vec::new();
+        for servers in self.idle_servers.values_mut() {
+            servers.retain(|server| {
+                if server.idle_since.elapsed() < idle_timeout {
+                    return true;
+                }
+                expired_servers.push(server.server_id);
+                false
+            });
+        }
+        expired_servers
+    }
+
+    /// Removes and returns the newest servers of the game modes that have more idle servers
+    /// than get_max_count returns for them
+    pub fn take_surplus_servers<F>(&mut self, get_max_count: F) -> Vec<ServerId>
+    where
+        F: Fn(&str) -> usize,
+    {
+        let mut surplus_servers = Vec::new();
+        for (game_mode_name, servers) in self.idle_servers.iter_mut() {
+            let max_count = get_max_count(game_mode_name);
+            if servers.len() > max_count {
+                surplus_servers.extend(servers.drain(max_count..).map(|server| server.server_id));
+            }
+        }
+        surplus_servers
+    }
+
+    pub fn take_all_servers(&mut self) -> Vec<ServerId> {
+        self.idle_servers
+            .drain()
+            .flat_map(|(_, servers)| servers)
+            .map(|server| server.server_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready_server_is_taken_first() {
+        let mut warm_pool = WarmPool::new();
+        warm_pool.add_server("default", 1);
+        warm_pool.add_server("default", 2);
+        warm_pool.add_server("other", 3);
+
+        assert_eq!(warm_pool.take_server("default", |id| id == 2), Some(2));
+        assert_eq!(warm_pool.take_server("default", |_| false), Some(1));
+        assert_eq!(warm_pool.take_server("default", |_| true), None);
+        assert_eq!(warm_pool.get_idle_servers_count("other"), 1);
+    }
+
+    #[test]
+    fn test_surplus_servers_are_taken_from_newest() {
+        let mut warm_pool = WarmPool::new();
+        warm_pool.add_server("default", 1);
+        warm_pool.add_server("default", 2);
+        warm_pool.add_server("default", 3);
+        warm_pool.add_server("removed", 4);
+
+        let mut surplus_servers =
+            warm_pool.take_surplus_servers(|name| if name == "default" { 1 } else { 0 });
+        surplus_servers.sort();
+        assert_eq!(surplus_servers, vec![2, 3, 4]);
+        assert_eq!(warm_pool.get_total_idle_servers_count(), 1);
+    }
+
+    #[test]
+    fn test_expired_and_stopped_servers_are_removed() {
+        let mut warm_pool = WarmPool::new();
+        warm_pool.add_server("default", 1);
+        warm_pool.add_server("default", 2);
+
+        warm_pool.remove_stopped_servers(|id| id != 1);
+        assert!(warm_pool
+            .take_expired_servers(Duration::from_secs(60))
+            .is_empty());
+        assert_eq!(warm_pool.take_expired_servers(Duration::ZERO), vec![2]);
+        assert_eq!(warm_pool.get_total_idle_servers_count(), 0);
+    }
+}